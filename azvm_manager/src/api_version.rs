@@ -0,0 +1,131 @@
+//! Overrides the REST API version each Azure client sends.
+//!
+//! The azure-mgmt crates pin an `api-version` per operation and offer no way
+//! to pick another, so the override is applied by rewriting that query
+//! parameter in the transport. Only the version on the wire changes: requests
+//! and responses still go through the crate's models, so properties added in
+//! a newer version are dropped and ones removed from it may fail to parse.
+
+use async_trait::async_trait;
+use azure_core::{HttpClient, Request, Response, TransportOptions, Url};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+
+const API_VERSION: &str = "api-version";
+
+/// The Azure services whose API version can be overridden.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Service {
+    Compute,
+    Network,
+    Resources,
+    Subscription,
+    Backup,
+    Vault,
+    Cost
+}
+
+impl Service {
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::Compute => "compute",
+            Service::Network => "network",
+            Service::Resources => "resources",
+            Service::Subscription => "subscription",
+            Service::Backup => "backup",
+            Service::Vault => "vault",
+            Service::Cost => "cost"
+        }
+    }
+}
+
+static OVERRIDES: OnceLock<BTreeMap<Service, String>> = OnceLock::new();
+
+/// Sets the API versions used by every client built afterwards. Services
+/// left out keep the version their crate was generated for.
+pub fn configure(overrides: BTreeMap<Service, String>) {
+    let _ = OVERRIDES.set(overrides);
+}
+
+/// Parses a `service=version` argument.
+pub fn parse(value: &str) -> Result<(Service, String), String> {
+    let Some((service, version)) = value.split_once('=') else {
+        return Err(format!("invalid API version '{value}', expected service=version"));
+    };
+
+    let service = Service::from_str(service.trim(), true)
+        .map_err(|_| format!("unknown service '{service}', expected one of compute, network, resources, subscription, backup, vault or cost"))?;
+    Ok((service, version.trim().to_owned()))
+}
+
+/// The transport to build `service`'s client with.
+pub fn transport(service: Service) -> TransportOptions {
+    let version = OVERRIDES.get().and_then(|overrides| overrides.get(&service)).cloned();
+    TransportOptions::new(Arc::new(ApiVersionClient {
+        inner: azure_core::new_http_client(),
+        version
+    }))
+}
+
+/// Sends requests through `inner`, replacing their `api-version` first.
+#[derive(Debug)]
+struct ApiVersionClient {
+    inner: Arc<dyn HttpClient>,
+    version: Option<String>
+}
+
+#[async_trait]
+impl HttpClient for ApiVersionClient {
+    async fn execute_request(&self, request: &Request) -> azure_core::Result<Response> {
+        let Some(version) = self.version.as_deref() else {
+            return self.inner.execute_request(request).await;
+        };
+
+        let mut request = request.clone();
+        let url = with_api_version(request.url(), version);
+        *request.url_mut() = url;
+        self.inner.execute_request(&request).await
+    }
+}
+
+/// `url` with its `api-version` set to `version`. URLs without one, such as
+/// SAS links, are left alone.
+fn with_api_version(url: &Url, version: &str) -> Url {
+    if !url.query_pairs().any(|(key, _)| key == API_VERSION) {
+        return url.clone();
+    }
+
+    let pairs: Vec<(String, String)> = url.query_pairs()
+        .map(|(key, value)| match key == API_VERSION {
+            true => (key.into_owned(), version.to_owned()),
+            false => (key.into_owned(), value.into_owned())
+        })
+        .collect();
+
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_only_the_api_version() {
+        let url = Url::parse("https://management.azure.com/subscriptions/sub/providers/Microsoft.Compute/virtualMachines?api-version=2023-07-01&$filter=x%20eq%201&statusOnly=true").unwrap();
+        let rewritten = with_api_version(&url, "2024-03-01");
+
+        let pairs: Vec<(String, String)> = rewritten.query_pairs().into_owned().collect();
+        assert_eq!(pairs, [
+            ("api-version".to_owned(), "2024-03-01".to_owned()),
+            ("$filter".to_owned(), "x eq 1".to_owned()),
+            ("statusOnly".to_owned(), "true".to_owned())
+        ]);
+        assert_eq!(rewritten.path(), url.path());
+
+        let sas = Url::parse("https://vm.blob.core.windows.net/logs/serial.log?sv=2021-08-06&sig=abc").unwrap();
+        assert_eq!(with_api_version(&sas, "2024-03-01"), sas);
+    }
+}
@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use azure_core::auth::{AccessToken, TokenCredential};
+use std::collections::HashMap;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::Mutex;
+
+/// Tokens expiring within this window are refreshed rather than reused.
+const EXPIRY_MARGIN: Duration = Duration::minutes(2);
+
+/// Wraps a credential so that a token is only requested once per set of
+/// scopes, until it is about to expire.
+#[derive(Debug)]
+pub struct CachedCredential {
+    inner: Arc<dyn TokenCredential>,
+    tokens: Mutex<HashMap<Vec<String>, AccessToken>>
+}
+
+impl CachedCredential {
+    pub fn new(inner: Arc<dyn TokenCredential>) -> Self {
+        Self {
+            inner,
+            tokens: Mutex::new(HashMap::new())
+        }
+    }
+}
+
+#[async_trait]
+impl TokenCredential for CachedCredential {
+    async fn get_token(&self, scopes: &[&str]) -> azure_core::Result<AccessToken> {
+        let key: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+
+        // Holding the lock while fetching means concurrent requests for the
+        // same scopes wait for the first one instead of all hitting `inner`.
+        let mut tokens = self.tokens.lock().await;
+        let cached = tokens.get(&key)
+            .filter(|token| token.expires_on > OffsetDateTime::now_utc() + EXPIRY_MARGIN);
+
+        if let Some(token) = cached {
+            return Ok(token.clone());
+        }
+
+        let token = self.inner.get_token(scopes).await?;
+        tokens.insert(key, token.clone());
+        Ok(token)
+    }
+
+    async fn clear_cache(&self) -> azure_core::Result<()> {
+        self.tokens.lock().await.clear();
+        self.inner.clear_cache().await
+    }
+}
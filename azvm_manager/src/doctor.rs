@@ -0,0 +1,189 @@
+use azure_core::auth::TokenCredential;
+use azure_mgmt_recoveryservicesbackup::Client as BackupClient;
+use colored::Colorize;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::sync::Arc;
+use store::Store;
+
+use crate::error::AppError;
+use crate::api_version::{self, Service};
+use crate::{credential, resource_client, retry, subscription_client, AuthMethod, Cli};
+
+const MANAGEMENT_SCOPE: &str = "https://management.azure.com/.default";
+
+enum Status {
+    Pass(String),
+    /// Not a problem for every command, e.g. a vault that only backups need.
+    Warn(String, &'static str),
+    Fail(String, String)
+}
+
+struct Check {
+    name: &'static str,
+    status: Status
+}
+
+/// Checks everything azvm needs to work and prints a checklist, with a hint
+/// on how to fix each failing check.
+pub async fn run(cli: &Cli, color: bool) -> Result<(), AppError> {
+    let mut checks = vec![
+        Check { name: "Azure CLI", status: check_az(cli.auth) },
+        Check { name: "Terminal colors", status: check_color(color) }
+    ];
+
+    let store = match Store::get_or_create().await {
+        Ok(mut store) => {
+            let profile = cli.profile.as_deref().map(|profile| store.override_profile(profile));
+            checks.push(Check {
+                name: "Store file",
+                status: match profile {
+                    Some(Err(e)) => Status::Fail(e.to_string(), "Create it with `profile create` or drop --profile".to_owned()),
+                    _ => Status::Pass(format!("{} (profile {})", store::store_path().display(), store.get_active_profile()))
+                }
+            });
+            Some(store)
+        },
+        Err(e) => {
+            checks.push(Check {
+                name: "Store file",
+                status: Status::Fail(e.to_string(), format!("Fix or remove {}", store::store_path().display()))
+            });
+            None
+        }
+    };
+
+    match credential(cli.auth) {
+        Ok(creds) => {
+            let token = creds.get_token(&[MANAGEMENT_SCOPE]).await;
+            let signed_in = token.is_ok();
+            checks.push(Check {
+                name: "Azure token",
+                status: match token {
+                    Ok(token) => Status::Pass(format!("valid until {}", token.expires_on)),
+                    Err(e) => Status::Fail(e.to_string(), auth_hint(cli.auth).to_owned())
+                }
+            });
+
+            if let (Some(store), true) = (store.as_ref(), signed_in) {
+                checks.extend(check_settings(store, creds).await);
+            }
+        },
+        Err(e) => checks.push(Check {
+            name: "Azure token",
+            status: Status::Fail(e.to_string(), auth_hint(cli.auth).to_owned())
+        })
+    }
+
+    let width = checks.iter().map(|check| check.name.len()).max().unwrap_or_default();
+    let mut failed = 0;
+    for check in &checks {
+        let (mark, detail, hint) = match &check.status {
+            Status::Pass(detail) => (paint("✓", color, |mark| mark.green()), detail.as_str(), None),
+            Status::Warn(detail, hint) => (paint("!", color, |mark| mark.yellow()), detail.as_str(), Some(*hint)),
+            Status::Fail(detail, hint) => {
+                failed += 1;
+                (paint("✗", color, |mark| mark.red()), detail.as_str(), Some(hint.as_str()))
+            }
+        };
+
+        println!("{mark} {:width$}  {detail}", check.name);
+        if let Some(hint) = hint {
+            println!("  {:width$}  {hint}", "");
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(AppError::ChecksFailed(failed))
+    }
+}
+
+fn paint(mark: &str, color: bool, style: impl Fn(&str) -> colored::ColoredString) -> String {
+    match color {
+        true => style(mark).to_string(),
+        false => mark.to_owned()
+    }
+}
+
+fn auth_hint(auth: AuthMethod) -> &'static str {
+    match auth {
+        AuthMethod::Cli | AuthMethod::Default => "Sign in with `az login`, or pick another method with --auth",
+        AuthMethod::ManagedIdentity => "Run on an Azure host with a managed identity, or pick another method with --auth",
+        AuthMethod::Env => "Set AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET, or pick another method with --auth"
+    }
+}
+
+fn check_az(auth: AuthMethod) -> Status {
+    let names: &[&str] = if cfg!(windows) { &["az.cmd", "az.exe"] } else { &["az"] };
+    let found = env::var_os("PATH")
+        .map(|path| env::split_paths(&path)
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .find(|candidate| Path::is_file(candidate)))
+        .unwrap_or_default();
+
+    match (found, auth) {
+        (Some(path), _) => Status::Pass(path.display().to_string()),
+        (None, AuthMethod::Cli) => Status::Fail(
+            "az was not found on PATH".to_owned(),
+            "Install the Azure CLI, or pick another method with --auth".to_owned()
+        ),
+        (None, _) => Status::Warn("az was not found on PATH".to_owned(), "Only needed for --auth cli")
+    }
+}
+
+fn check_color(color: bool) -> Status {
+    let dumb = env::var("TERM").is_ok_and(|term| term == "dumb");
+    if !color {
+        Status::Warn("disabled by --no-color or NO_COLOR".to_owned(), "Unset NO_COLOR to get colored tables")
+    } else if !io::stdout().is_terminal() || dumb {
+        Status::Warn("output is not a color terminal".to_owned(), "Colors only show when writing to a terminal")
+    } else {
+        Status::Pass("supported".to_owned())
+    }
+}
+
+/// Checks that the subscription, resource group and vault in the store exist.
+async fn check_settings(store: &Store, creds: Arc<dyn TokenCredential>) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let Some(sub_id) = store.get_subscription_id() else {
+        checks.push(Check {
+            name: "Subscription",
+            status: Status::Fail("not set".to_owned(), "Set one with --set-sub".to_owned())
+        });
+        return checks;
+    };
+
+    let status = match subscription_client(creds.clone()).subscriptions_client().get(sub_id).await {
+        Ok(sub) => Status::Pass(sub.display_name.unwrap_or_else(|| sub_id.to_owned())),
+        Err(e) => Status::Fail(format!("{sub_id}: {e}"), "Pick one listed by `sub list` with --set-sub".to_owned())
+    };
+    checks.push(Check { name: "Subscription", status });
+
+    let status = match store.get_resource_group() {
+        Some(group) => match resource_client(creds.clone()).resource_groups_client().get(group, sub_id).await {
+            Ok(_) => Status::Pass(group.to_owned()),
+            Err(e) => Status::Fail(format!("{group}: {e}"), "Pick one listed by `rg list` with --set-rg".to_owned())
+        },
+        None => Status::Fail("not set".to_owned(), "Set one with --set-rg".to_owned())
+    };
+    checks.push(Check { name: "Resource group", status });
+
+    let status = match (store.get_vault_name(), store.get_vault_resource_group()) {
+        (Some(vault), Some(group)) => {
+            let client = BackupClient::builder(creds)
+                .retry(retry::options())
+                .transport(api_version::transport(Service::Backup))
+                .build();
+
+            match client.backup_resource_vault_configs_client().get(vault, group, sub_id).await {
+                Ok(_) => Status::Pass(format!("{vault} in {group}")),
+                Err(e) => Status::Fail(format!("{vault} in {group}: {e}"), "Pick one listed by `vault list` with --set-vault and --set-vault-rg".to_owned())
+            }
+        },
+        _ => Status::Warn("not set".to_owned(), "Only recovery commands need --set-vault and --set-vault-rg")
+    };
+    checks.push(Check { name: "Recovery vault", status });
+    checks
+}
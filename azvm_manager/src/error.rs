@@ -1,18 +1,214 @@
+use azure_core::StatusCode;
 use std::fmt::{self, Formatter, Display};
+use std::path::PathBuf;
+use store::StoreError;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum AppError {
     NoSub,
     NoRg,
+    NoVault,
+    NoVaultRg,
+    ConfirmationRequired,
+    NoRecoveryPoint,
+    NoRecoveryPoints(String),
+    InvalidSelection(String),
+    MissingOperation,
+    InvalidOperation(String),
+    OperationFailed(String),
+    SizeUnavailable { size: String, available: Vec<String> },
+    UnknownSubscription(String),
+    AmbiguousSubscription(String, Vec<String>),
+    Validation(String, azure_core::Error),
+    NoMatchingVms,
+    NoMatchingGroups,
+    NotFound { kind: &'static str, name: String },
+    Timeout { seconds: u64, pending: Vec<String> },
+    NoLastVms(String),
+    Incomplete { failed: Vec<String>, timed_out: Vec<String> },
+    NamesFile(String, std::io::Error),
+    BootDiagnosticsDisabled(String),
+    InvalidResourceId(String),
+    ExcludedNotFound(Vec<String>),
+    ExportFailed { group: String, reason: String },
+    ChecksFailed(usize),
+    OutputFileExists(PathBuf),
+    UnknownVms(Vec<String>),
+    InvalidTimeRange { since: chrono::DateTime<chrono::FixedOffset>, until: chrono::DateTime<chrono::FixedOffset> },
+    Azure(azure_core::Error),
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    Store(StoreError),
 }
 
-impl std::error::Error for AppError {}
+impl AppError {
+    /// Maps a 404 from Azure to `NotFound` for the named resource, leaving
+    /// any other error intact.
+    pub fn or_not_found<'a>(kind: &'static str, name: &'a str) -> impl FnOnce(azure_core::Error) -> AppError + 'a {
+        move |e| match e.as_http_error().map(|http| http.status()) {
+            Some(StatusCode::NotFound) => AppError::NotFound { kind, name: name.to_owned() },
+            _ => AppError::Azure(e)
+        }
+    }
+
+    /// Whether Azure rejected the request with `429 Too Many Requests`, after
+    /// the client's own retries.
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            AppError::Azure(e) => e.as_http_error().is_some_and(|http| http.status() == StatusCode::TooManyRequests),
+            _ => false
+        }
+    }
+
+    /// The process exit code reported for this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::NoSub => 3,
+            AppError::NoRg => 4,
+            AppError::NoVault => 10,
+            AppError::NoVaultRg => 11,
+            AppError::ConfirmationRequired => 9,
+            AppError::NoRecoveryPoint => 12,
+            AppError::NoRecoveryPoints(_) => 13,
+            AppError::InvalidSelection(_) => 14,
+            AppError::MissingOperation => 15,
+            AppError::InvalidOperation(_) => 17,
+            AppError::OperationFailed(_) => 16,
+            AppError::SizeUnavailable { .. } => 18,
+            AppError::UnknownSubscription(_) => 19,
+            AppError::AmbiguousSubscription(..) => 20,
+            AppError::Validation(..) => 21,
+            AppError::NoMatchingVms => 22,
+            AppError::NoMatchingGroups => 30,
+            AppError::NotFound { .. } => 23,
+            AppError::Timeout { .. } => 24,
+            AppError::NoLastVms(_) => 25,
+            AppError::Incomplete { .. } => 26,
+            AppError::NamesFile(..) => 27,
+            AppError::BootDiagnosticsDisabled(_) => 28,
+            AppError::InvalidResourceId(_) => 29,
+            AppError::ExcludedNotFound(_) => 31,
+            AppError::ExportFailed { .. } => 32,
+            AppError::ChecksFailed(_) => 33,
+            AppError::OutputFileExists(_) => 34,
+            AppError::UnknownVms(_) => 35,
+            AppError::InvalidTimeRange { .. } => 36,
+            AppError::Azure(_) => 5,
+            AppError::Json(_) => 6,
+            AppError::Io(_) => 7,
+            AppError::Store(_) => 8,
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Azure(e) => Some(e),
+            AppError::Validation(_, e) => Some(e),
+            AppError::NamesFile(_, e) => Some(e),
+            AppError::Json(e) => Some(e),
+            AppError::Io(e) => Some(e),
+            AppError::Store(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl Display for AppError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             AppError::NoSub => write!(f, "No subscription specified"),
             AppError::NoRg => write!(f, "No resource group specified"),
+            AppError::NoVault => write!(f, "No Recovery Services vault specified"),
+            AppError::NoVaultRg => write!(f, "No Recovery Services vault resource group specified"),
+            AppError::ConfirmationRequired => write!(f, "Confirmation required but stdin is not a terminal, pass --yes to proceed"),
+            AppError::NoRecoveryPoint => write!(f, "No recovery point specified and stdin is not a terminal"),
+            AppError::NoRecoveryPoints(vm) => write!(f, "No recovery points found for {vm}"),
+            AppError::InvalidSelection(answer) => write!(f, "Invalid selection: {answer}"),
+            AppError::MissingOperation => write!(f, "Azure did not return an operation to track"),
+            AppError::InvalidOperation(url) => write!(f, "Unable to read an operation id from {url}"),
+            AppError::OperationFailed(detail) => write!(f, "Operation failed: {detail}"),
+            AppError::SizeUnavailable { size, available } => {
+                write!(f, "Size {size} is not available for this VM. Available sizes: {}", available.join(", "))
+            },
+            AppError::UnknownSubscription(name) => write!(f, "No subscription named '{name}'"),
+            AppError::AmbiguousSubscription(name, ids) => {
+                write!(f, "Subscription name '{name}' is ambiguous, matching: {}", ids.join(", "))
+            },
+            AppError::Validation(what, e) => {
+                write!(f, "Failed to validate {what}: {e}. Pass --no-validate to save it anyway")
+            },
+            AppError::NoMatchingVms => write!(f, "No virtual machines matched the filter"),
+            AppError::NoMatchingGroups => write!(f, "No resource groups matched the filter"),
+            AppError::NotFound { kind, name } => write!(f, "The {kind} '{name}' was not found"),
+            AppError::Timeout { seconds, pending } => {
+                write!(f, "Timed out after {seconds}s waiting for: {}", pending.join(", "))
+            },
+            AppError::NoLastVms(group) => write!(f, "No previous start/stop/restart recorded for resource group {group}"),
+            AppError::Incomplete { failed, timed_out } => {
+                write!(f, "Not every VM reached the target state")?;
+                if !failed.is_empty() {
+                    write!(f, ". Failed: {}", failed.join(", "))?;
+                }
+                if !timed_out.is_empty() {
+                    write!(f, ". Timed out: {}", timed_out.join(", "))?;
+                }
+                Ok(())
+            },
+            AppError::NamesFile(path, e) => write!(f, "Failed to read VM names from {path}: {e}"),
+            AppError::BootDiagnosticsDisabled(vm) => {
+                write!(f, "Boot diagnostics are not enabled on {vm}, enable them in the VM's settings first")
+            },
+            AppError::InvalidResourceId(id) => write!(
+                f,
+                "'{id}' is not a VM resource ID, expected /subscriptions/{{sub}}/resourceGroups/{{group}}/providers/Microsoft.Compute/virtualMachines/{{name}}"
+            ),
+            AppError::ExcludedNotFound(names) => write!(
+                f,
+                "Excluded VMs not found in the targeted resource groups: {}. Pass --ignore-missing to skip them",
+                names.join(", ")
+            ),
+            AppError::ExportFailed { group, reason } => write!(f, "Failed to export resource group {group}: {reason}"),
+            AppError::ChecksFailed(1) => write!(f, "1 check failed"),
+            AppError::ChecksFailed(count) => write!(f, "{count} checks failed"),
+            AppError::OutputFileExists(path) => {
+                write!(f, "{} already exists, pass --overwrite to replace it or --append to add to it", path.display())
+            },
+            AppError::UnknownVms(names) => write!(
+                f,
+                "VMs not found in the targeted resource groups: {}. Pass --ignore-missing to skip them",
+                names.join(", ")
+            ),
+            AppError::InvalidTimeRange { since, until } => write!(f, "--since ({since}) is after --until ({until})"),
+            AppError::Azure(e) => write!(f, "Azure request failed: {e}"),
+            AppError::Json(e) => write!(f, "Invalid JSON: {e}"),
+            AppError::Io(e) => write!(f, "I/O error: {e}"),
+            AppError::Store(e) => write!(f, "Store error: {e}"),
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<azure_core::Error> for AppError {
+    fn from(e: azure_core::Error) -> Self {
+        AppError::Azure(e)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::Json(e)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Io(e)
+    }
+}
+
+impl From<StoreError> for AppError {
+    fn from(e: StoreError) -> Self {
+        AppError::Store(e)
+    }
+}
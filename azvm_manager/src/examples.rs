@@ -0,0 +1,64 @@
+use std::env;
+use std::path::Path;
+
+/// A common invocation, without the program name, and what it does.
+struct Example {
+    args: &'static str,
+    about: &'static str
+}
+
+const EXAMPLES: [Example; 24] = [
+    Example { args: "sub list", about: "List every subscription you can access" },
+    Example { args: "sub get -i \"My Subscription\"", about: "Show a subscription by display name" },
+    Example { args: "rg list --all-subs", about: "List the resource groups of every subscription" },
+    Example { args: "rg get -g my-group --with-vms", about: "Show a resource group and how many of its VMs are running" },
+    Example { args: "rg get -g my-group --export template.json", about: "Save the ARM template of a resource group" },
+    Example { args: "vm list -g my-group --sort-by status", about: "List the VMs of a resource group by power state" },
+    Example { args: "vm list-all -t env=dev", about: "List the VMs tagged env=dev across the subscription" },
+    Example { args: "vm list-all -l eastus -t env=prod --stopped-only", about: "List the stopped prod VMs in eastus" },
+    Example { args: "vm get -n web1 -g my-group", about: "Show a single VM along with its addresses" },
+    Example { args: "vm get -n web1 -g my-group --nsg", about: "List the inbound rules that allow traffic to a VM" },
+    Example { args: "vm get -n web1 -g my-group --disks", about: "Check whether a VM's disks are encrypted" },
+    Example { args: "vm start -n web1,web2 -g my-group", about: "Start two VMs and wait until they are running" },
+    Example { args: "vm stop -g my-group", about: "Pick the VMs to stop from a list" },
+    Example { args: "vm stop --again --yes", about: "Stop the VMs from the last start, without prompting" },
+    Example { args: "vm stop -n web1 --no-deallocate", about: "Power off a VM but keep it allocated, and its IP" },
+    Example { args: "vm restart -n @vms.txt --dry-run", about: "Show which VMs listed in vms.txt would be restarted" },
+    Example { args: "vm restart -g my-group --no-wait", about: "Restart every VM in a group without waiting for them" },
+    Example { args: "vm resize -n web1 --size Standard_D4s_v3", about: "Resize a VM" },
+    Example { args: "vm tag -n web1 --add owner=ops --remove temp", about: "Add and remove tags on a VM" },
+    Example { args: "vm status -g my-group -o json", about: "Print the power state of each VM as JSON" },
+    Example { args: "recovery list -n web1", about: "List the recovery points of a VM" },
+    Example { args: "recovery list -n web1 --since 2024-05-01T00:00:00Z", about: "List the recovery points of a VM taken since May 2024" },
+    Example { args: "recovery trigger-backup -n web1 --retain-days 7", about: "Back up a VM now and keep it for a week" },
+    Example { args: "vault list", about: "List the Recovery Services vaults to pick one for --set-vault" },
+];
+
+/// The name this program was invoked as, so examples can be pasted as-is.
+fn program() -> String {
+    env::args_os()
+        .next()
+        .and_then(|arg| Path::new(&arg).file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_owned())
+}
+
+/// Renders the examples for `command` (e.g. `vm` or `vm start`) as a help
+/// section, or an empty string when there are none.
+pub fn render(command: &str) -> String {
+    let examples: Vec<_> = EXAMPLES.iter()
+        .filter(|example| {
+            example.args.strip_prefix(command).is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+        })
+        .collect();
+
+    if examples.is_empty() {
+        return String::new();
+    }
+
+    let program = program();
+    let lines: Vec<_> = examples.iter()
+        .map(|example| format!("  # {}\n  {program} {}", example.about, example.args))
+        .collect();
+
+    format!("Examples:\n{}", lines.join("\n\n"))
+}
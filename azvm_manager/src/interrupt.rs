@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Exit code after Ctrl-C, following the shell convention of 128 + SIGINT.
+pub const EXIT_CODE: u8 = 130;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static PENDING: Mutex<BTreeMap<u64, String>> = Mutex::new(BTreeMap::new());
+
+/// An operation that has been sent to Azure and not yet finished, listed
+/// if the run is interrupted until the guard is dropped.
+pub struct PendingGuard(u64);
+
+impl PendingGuard {
+    pub fn update(&self, description: impl Into<String>) {
+        if let Ok(mut pending) = PENDING.lock() {
+            pending.insert(self.0, description.into());
+        }
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = PENDING.lock() {
+            pending.remove(&self.0);
+        }
+    }
+}
+
+/// Records `description` as pending until the returned guard is dropped.
+pub fn pending(description: impl Into<String>) -> PendingGuard {
+    let guard = PendingGuard(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    guard.update(description);
+    guard
+}
+
+/// The operations pending right now, oldest first.
+pub fn snapshot() -> Vec<String> {
+    PENDING.lock()
+        .map(|pending| pending.values().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Reports an interrupted run. `pending` should be taken before the run is
+/// dropped, as dropping it clears the guards.
+pub fn report(pending: &[String]) {
+    // The spinners hide the cursor while they draw.
+    if io::stdout().is_terminal() {
+        print!("\x1B[?25h");
+        let _ = io::stdout().flush();
+    }
+
+    eprintln!("Interrupted");
+    if !pending.is_empty() {
+        eprintln!("Still pending:");
+        for operation in pending {
+            eprintln!("  {operation}");
+        }
+        eprintln!("Requests already sent to Azure will carry on server-side, check on them with vm status or the portal.");
+    }
+}
@@ -1,390 +1,2582 @@
-use azure_identity::AzureCliCredential;
-use azure_core::{RetryOptions, ExponentialRetryOptions, auth::TokenCredential};
-use clap::{Parser, Subcommand, Args};
-use futures_util::TryStreamExt;
+use azure_identity::{
+    AzureCliCredential, DefaultAzureCredential, EnvironmentCredential, ImdsManagedIdentityCredential,
+    TokenCredentialOptions
+};
+use azure_core::{Method, Request, Response, StatusCode, Url, auth::TokenCredential};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, Args, ValueEnum};
+use clap_complete::Shell;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::process::ExitCode;
 use std::sync::Arc;
-use log::debug;
+use log::{debug, info, warn, LevelFilter};
 use store::Store;
-use azure_mgmt_resources::{Client as ResourceClient, models::ResourceGroup};
+use azure_mgmt_costmanagement::{Client as CostClient, models::QueryDefinition};
+use azure_mgmt_resources::{Client as ResourceClient, models::{ExportTemplateRequest, ResourceGroup}};
+use azure_mgmt_recoveryservices::{Client as VaultClient, models::Vault as RecoveryVault};
 use azure_mgmt_subscription::{Client as SubscriptionClient, models::Subscription};
+use azure_mgmt_recoveryservicesbackup::{
+    Client as BackupClient,
+    models::{operation_status::Status as OperationState, BackupRequestResource, ProtectedItemResource, RecoveryPointResource, RestoreRequestResource}
+};
+use serde_json::json;
+use time::OffsetDateTime;
+use chrono::{DateTime, FixedOffset};
 use tokio::time::{sleep_until, Duration, Instant};
-use dsp::{display_rg, display_sub, display_vm, Output};
-use spinoff::{Spinner, spinners, Color};
-
-use crate::vm_client::{VmClient, VmCommand};
-
+use dsp::{
+    display_boot_diagnostics, display_config, display_disks, display_image_aliases, display_instance_view, display_nsg_rules, display_protected_item, display_recovery_points, display_rg, display_rg_by_sub, display_rg_with_vms,
+    display_sub, display_sub_with_spending, display_tags, display_vm, display_vm_actions, display_vm_all_groups, display_vm_results, display_vm_status, display_vm_with_addresses,
+    display_timings, display_vaults, power_state, Config, DisplayOptions, Format, ImageAlias, Output, OutputFile, Spending, TableStyle, SubscriptionGroup, VmAction, VmColumn, VmCounts, VmOperationResult, VmStatus
+};
+use colored::Colorize;
+use dialoguer::{theme::ColorfulTheme, MultiSelect};
+
+use crate::api_version::Service;
+use crate::credential::CachedCredential;
+use crate::spinner::SpinnerGuard;
+use crate::vm_client::{Progress, ResourceId, StateFilter, VmClient, VmCommand, VmOperations, VmFilter, VmSort, DEFAULT_CONCURRENCY, DEFAULT_MAX_IN_FLIGHT, IMAGE_ALIASES};
+
+mod api_version;
+mod credential;
+mod doctor;
 mod error;
+mod examples;
+mod interrupt;
+mod operation;
+mod retry;
+mod spinner;
+mod timings;
 mod vm_client;
 
+/// The backup fabric used for Azure IaaS VMs.
+const BACKUP_FABRIC: &str = "Azure";
+
+/// How long to wait for long-running operations by default, in seconds.
+const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// How often to check on VMs while waiting for them to change state.
+#[derive(Debug, Clone, Copy)]
+struct PollInterval {
+    initial: Duration,
+    max: Duration
+}
+
+impl PollInterval {
+    /// The delay after `current`, growing gently (2s, 5s, 10s by default) so
+    /// long operations make fewer calls.
+    fn next(self, current: Duration) -> Duration {
+        (current * 5 / 2).min(self.max).max(self.initial)
+    }
+
+    /// The delay after `current` while Azure is throttling, doubling up to a
+    /// minute, or up to `max` when that is longer.
+    fn throttled(self, current: Duration) -> Duration {
+        (current * 2).min(MAX_THROTTLED_POLL_INTERVAL.max(self.max)).max(self.max)
+    }
+}
+
+/// The longest to wait between polls while Azure is throttling them.
+const MAX_THROTTLED_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a VM may report no power state at all before we stop waiting on it.
+const NO_POWER_STATE_GRACE: Duration = Duration::from_secs(120);
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Sets the Azure subscription ID.
+    /// Sets the Azure subscription by ID or display name.
     #[arg(long)]
     set_sub: Option<String>,
 
-    /// Set the Azure resource group.
+    /// Set the Azure resource group, remembered per subscription.
     #[arg(long)]
     set_rg: Option<String>,
 
+    /// Sets the Recovery Services vault used for backups.
+    #[arg(long)]
+    set_vault: Option<String>,
+
+    /// Sets the resource group of the Recovery Services vault.
+    #[arg(long)]
+    set_vault_rg: Option<String>,
+
+    /// Overrides the REST API version of a service, e.g. `compute=2024-03-01`,
+    /// for every later run. An empty version goes back to the default.
+    #[arg(long, value_name = "SERVICE=VERSION", value_parser = api_version::parse)]
+    set_api_version: Vec<(Service, String)>,
+
+    /// Saves --set-sub/--set-rg without checking that they exist in Azure.
+    #[arg(long)]
+    no_validate: bool,
+
+    /// Uses the named profile for this invocation instead of the active one.
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Increases logging: -v for info, -vv for debug, -vvv for trace. `RUST_LOG` takes precedence.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Uses this subscription, by ID or display name, for this command only.
+    /// Command options such as `--sub-id` still take precedence.
+    #[arg(long, global = true)]
+    subscription: Option<String>,
+
+    /// Uses this resource group for this command only. Command options such
+    /// as `--group` still take precedence.
+    #[arg(long, global = true)]
+    resource_group: Option<String>,
+
+    /// Sets the output format.
+    #[arg(short, long, value_enum, default_value_t = Format::Table, global = true)]
+    output: Format,
+
+    /// Sets the borders of tables, e.g. markdown for pasting into docs.
+    #[arg(long, value_enum, default_value_t = TableStyle::Modern, global = true)]
+    style: TableStyle,
+
+    /// Maximum number of start/stop/restart requests sent at once, capped by --concurrency.
+    #[arg(long, default_value_t = DEFAULT_MAX_IN_FLIGHT, global = true)]
+    max_in_flight: usize,
+
+    /// Retries failed Azure requests up to this many times. Defaults to the SDK's policy.
+    #[arg(long, global = true)]
+    max_retries: Option<u32>,
+
+    /// Waits this many milliseconds before the first retry, doubling after each.
+    #[arg(long, global = true)]
+    retry_delay: Option<u64>,
+
+    /// Overrides the REST API version of a service for this command only, e.g.
+    /// `compute=2024-03-01`. Services are compute, network, resources,
+    /// subscription, backup, vault and cost. Responses are still read with the
+    /// built-in models, so properties new in that version are not shown.
+    #[arg(long, value_name = "SERVICE=VERSION", value_parser = api_version::parse, global = true)]
+    api_version: Vec<(Service, String)>,
+
+    /// Maximum number of Azure requests made at once, to avoid throttling.
+    #[arg(long, default_value_t = DEFAULT_CONCURRENCY, global = true)]
+    concurrency: usize,
+
+    /// Gives up waiting for start/stop/restart or restore operations after this many seconds.
+    #[arg(long, default_value_t = DEFAULT_TIMEOUT_SECS, global = true)]
+    timeout: u64,
+
+    /// How to authenticate with Azure. `env` reads a service principal from
+    /// AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET.
+    #[arg(long, value_enum, default_value_t = AuthMethod::Cli, global = true)]
+    auth: AuthMethod,
+
+    /// Selects the columns of VM tables, e.g. `--columns name,status`.
+    #[arg(long, value_enum, value_delimiter = ',', global = true)]
+    columns: Vec<VmColumn>,
+
+    /// Shows every VM table column, including tags, disks, provisioning state and zone, at full
+    /// width instead of truncating them to the terminal.
+    #[arg(long, global = true)]
+    wide: bool,
+
+    /// Hides spinners and the tables printed after start/stop/restart/resize/restore.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Prints JSON output on one line instead of indented.
+    #[arg(long, global = true)]
+    compact: bool,
+
+    /// Writes tables, JSON or CSV to this file, without colors, instead of the terminal.
+    #[arg(long, value_name = "PATH", global = true)]
+    output_file: Option<PathBuf>,
+
+    /// Replaces the --output-file if it already exists.
+    #[arg(long, requires = "output_file", conflicts_with = "append", global = true)]
+    overwrite: bool,
+
+    /// Adds to the end of the --output-file if it already exists.
+    #[arg(long, requires = "output_file", global = true)]
+    append: bool,
+
+    /// Prints how long each kind of Azure call took once the command finishes.
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Disables colored output. Also honored via the `NO_COLOR` environment variable.
+    #[arg(long, global = true)]
+    no_color: bool,
+
     #[command(subcommand)]
     command: Option<Cmd>
 }
 
+/// The credential used to obtain Azure tokens.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum AuthMethod {
+    /// The logged in Azure CLI account.
+    Cli,
+    /// The Azure SDK's default chain: environment, managed identity, then Azure CLI.
+    Default,
+    /// The managed identity of the Azure host this runs on.
+    ManagedIdentity,
+    /// A service principal read from environment variables.
+    Env
+}
+
 #[derive(Subcommand, Debug)]
 enum Cmd { 
     /// A set of commands for Azure subscriptions.
+    #[command(after_help = examples::render("sub"))]
     Sub(SubArgs),
+    #[command(after_help = examples::render("rg"))]
     Rg(RgArgs),
-    Vm(VmArgs)
+    #[command(after_help = examples::render("vm"))]
+    Vm(VmArgs),
+    /// A set of commands for Azure Backup.
+    #[command(after_help = examples::render("recovery"))]
+    Recovery(RecoveryArgs),
+    /// A set of commands for Recovery Services vaults.
+    #[command(after_help = examples::render("vault"))]
+    Vault(VaultArgs),
+    /// Manage named configuration profiles.
+    Profile(ProfileArgs),
+    /// Inspect or reset the stored configuration.
+    Config(ConfigArgs),
+    /// Checks sign-in, the store file and the configured resources, with hints for fixing each.
+    Doctor,
+    /// Prints a shell completion script.
+    #[command(hide = true)]
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell
+    }
 }
 
 #[derive(Args, Debug)]
-struct VmArgs {
+struct ConfigArgs {
     #[command(subcommand)]
-    command: VmCmd
+    command: ConfigCmd
 }
 
 #[derive(Subcommand, Debug)]
-enum VmCmd {
-    Get {
-        #[arg(short, long)]
-        name: String,
+enum ConfigCmd {
+    /// Displays the settings of the active profile.
+    Show,
+    /// Resets all settings of the active profile.
+    Clear
+}
+
+#[derive(Args, Debug)]
+struct RecoveryArgs {
+    #[command(subcommand)]
+    command: RecoveryCmd
+}
+
+#[derive(Subcommand, Debug)]
+enum RecoveryCmd {
+    /// Lists the VMs protected by a Recovery Services vault, or the recovery
+    /// points of one VM, newest first.
+    #[command(after_help = examples::render("recovery list"))]
+    List {
+        #[arg(long)]
+        vault_name: Option<String>,
+
+        #[arg(long)]
+        vault_group: Option<String>,
 
         #[arg(short, long)]
+        sub_id: Option<String>,
+
+        /// Lists the recovery points of this VM instead of the protected VMs.
+        #[arg(short = 'n', long)]
+        vm_name: Option<String>,
+
+        /// The resource group of the VM.
+        #[arg(short, long, requires = "vm_name")]
         group: Option<String>,
 
-        #[arg(short, long)]
-        sub_id: Option<String>
+        /// Only lists recovery points taken at or after this RFC 3339 time, e.g. 2024-05-01T00:00:00Z.
+        #[arg(long, requires = "vm_name", value_parser = parse_timestamp)]
+        since: Option<DateTime<FixedOffset>>,
+
+        /// Only lists recovery points taken at or before this RFC 3339 time.
+        #[arg(long, requires = "vm_name", value_parser = parse_timestamp)]
+        until: Option<DateTime<FixedOffset>>,
+
+        /// The backup fabric that holds the VM's protection container.
+        #[arg(long, default_value = BACKUP_FABRIC, requires = "vm_name")]
+        fabric: String
     },
-    List {
+    /// Restores a VM to its original location from a recovery point.
+    Restore {
+        #[arg(long)]
+        vault_name: Option<String>,
+
+        #[arg(long)]
+        vault_group: Option<String>,
+
+        /// The resource group of the VM.
         #[arg(short, long)]
         group: Option<String>,
 
         #[arg(short, long)]
-        sub_id: Option<String>
-    },
-    ListAll {
+        sub_id: Option<String>,
+
+        #[arg(short = 'n', long)]
+        vm_name: String,
+
+        /// The recovery point to restore. Prompts with a list when omitted.
         #[arg(short, long)]
-        sub_id: Option<String>
+        recovery_point: Option<String>,
+
+        /// The backup fabric that holds the VM's protection container.
+        #[arg(long, default_value = BACKUP_FABRIC)]
+        fabric: String
     },
-    Start {
-        #[arg(short, long, num_args = 1.., value_delimiter = ',')]
-        names: Option<Vec<String>>,
+    /// Takes an on-demand backup of VMs that are already protected.
+    #[command(visible_alias = "now", after_help = examples::render("recovery trigger-backup"))]
+    TriggerBackup {
+        #[arg(long)]
+        vault_name: Option<String>,
 
+        #[arg(long)]
+        vault_group: Option<String>,
+
+        /// The resource group of the VMs.
         #[arg(short, long)]
         group: Option<String>,
 
         #[arg(short, long)]
-        sub_id: Option<String>
+        sub_id: Option<String>,
+
+        /// VM names, or @FILE to read them from a file, one per line.
+        #[arg(short, long, required = true, num_args = 1.., value_delimiter = ',')]
+        names: Vec<String>,
+
+        /// How many days to keep the recovery point.
+        #[arg(long, default_value_t = 30)]
+        retain_days: u32,
+
+        /// The backup fabric that holds the VMs' protection containers.
+        #[arg(long, default_value = BACKUP_FABRIC)]
+        fabric: String
     },
-    Stop {
-        #[arg(short, long, num_args = 1, value_delimiter = ',')]
-        names: Option<Vec<String>>,
+    /// Stops backing up VMs, keeping their recovery points unless --delete-data is passed.
+    Disable {
+        #[arg(long)]
+        vault_name: Option<String>,
+
+        #[arg(long)]
+        vault_group: Option<String>,
 
+        /// The resource group of the VMs.
         #[arg(short, long)]
         group: Option<String>,
 
         #[arg(short, long)]
-        sub_id: Option<String>
+        sub_id: Option<String>,
+
+        /// VM names, or @FILE to read them from a file, one per line.
+        #[arg(short, long, required = true, num_args = 1.., value_delimiter = ',')]
+        names: Vec<String>,
+
+        /// Also deletes every recovery point of the VMs. This cannot be undone.
+        #[arg(long, requires = "yes")]
+        delete_data: bool,
+
+        /// Confirms deleting backup data.
+        #[arg(short, long)]
+        yes: bool,
+
+        /// The backup fabric that holds the VMs' protection containers.
+        #[arg(long, default_value = BACKUP_FABRIC)]
+        fabric: String
     }
 }
 
 #[derive(Args, Debug)]
-struct RgArgs {
+struct VaultArgs {
     #[command(subcommand)]
-    command: RgCmd
+    command: VaultCmd
 }
 
 #[derive(Subcommand, Debug)]
-enum RgCmd {
-    Get {
+enum VaultCmd {
+    /// Lists the Recovery Services vaults of a resource group, or of the
+    /// whole subscription when no group is given.
+    List {
         #[arg(short, long)]
         group: Option<String>,
 
-        #[arg(short, long)]
-        sub_id: Option<String>
-    },
-    List {
         #[arg(short, long)]
         sub_id: Option<String>
     }
 }
 
 #[derive(Args, Debug)]
-struct SubArgs {
+struct ProfileArgs {
     #[command(subcommand)]
-    command: SubCmd
+    command: ProfileCmd
 }
 
 #[derive(Subcommand, Debug)]
-enum SubCmd {
-    /// Displays information about about a subscription.
-    Get {
-        /// Displays information about the specified subscription, 
-        /// else displays information about the currently selected subscription.
-        #[arg(short, long)]
-        id: Option<String>
+enum ProfileCmd {
+    /// Creates a new, empty profile.
+    Create {
+        name: String
     },
-    /// Displays information about all subscriptions.
+    /// Makes the specified profile the active one.
+    Switch {
+        name: String
+    },
+    /// Lists all profiles, marking the active one.
     List
 }
 
-async fn handle_globals(cli: &Cli, store: &mut Store) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(sub_id) = cli.set_sub.as_deref() {
-        debug!("Setting subscription to: {sub_id}");
-        store.set_subscription_id(sub_id);
-    }
+#[derive(Args, Debug)]
+struct VmArgs {
+    #[command(subcommand)]
+    command: VmCmd
+}
 
-    if let Some(rg) = cli.set_rg.as_deref() {
-        debug!("Setting resource group to: {rg}");
-        store.set_resource_group(rg); 
-    }
+#[derive(Subcommand, Debug)]
+enum VmCmd {
+    #[command(after_help = examples::render("vm get"))]
+    Get {
+        #[arg(short, long, required_unless_present = "id")]
+        name: Option<String>,
 
-    if cli.set_sub.is_some() || cli.set_rg.is_some() {
-        debug!("Saving store file");
-        store.save().await.expect("Failed to save store file");
-    }
-    Ok(())
-}
+        #[arg(short, long)]
+        group: Option<String>,
 
-async fn process_sub_cmd(args: SubArgs, store: &Store, creds: Arc<dyn TokenCredential>) -> Result<(), Box<dyn std::error::Error>> {
+        #[arg(short, long)]
+        sub_id: Option<String>,
 
-    let client = SubscriptionClient::builder(creds)
-        .retry(RetryOptions::exponential(ExponentialRetryOptions::default()))
-        .build();
+        /// The full ARM resource ID of the VM, instead of its name, group and subscription.
+        #[arg(long, conflicts_with_all = ["name", "group", "sub_id"])]
+        id: Option<String>,
 
-    match &args.command {
-        SubCmd::Get { id } => {
-            let sub_id = match id.as_deref() {
-                Some(id) => id,
-                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?,
-            };
+        /// Prints the complete VM model as pretty JSON instead of the table.
+        #[arg(long)]
+        raw: bool,
 
-            let sub = client.subscriptions_client()
-                .get(sub_id)
-                .await?;
+        /// Shows the inbound rules that allow traffic to the VM's NICs instead of the VM.
+        #[arg(long, conflicts_with = "raw")]
+        nsg: bool,
 
-            display_sub(Output::Single(&sub));
-        },
-        SubCmd::List => {
-            let subs: Vec<Subscription> = client.subscriptions_client()
-                .list()
-                .into_stream()
-                .try_collect::<Vec<_>>()
-                .await?
-                .into_iter()
-                .flat_map(|subs| subs.value)
-                .collect();
+        /// Shows the VM's disks and how each is encrypted instead of the VM.
+        #[arg(long, conflicts_with_all = ["raw", "nsg"])]
+        disks: bool
+    },
+    #[command(after_help = examples::render("vm list"))]
+    List {
+        #[arg(short, long)]
+        group: Option<String>,
 
-            display_sub(Output::Multiple(&subs));
-        }
-    }
-    Ok(())
-}
+        #[arg(short, long)]
+        sub_id: Option<String>,
 
-async fn process_rg_cmd(args: RgArgs, store: &Store, creds: Arc<dyn TokenCredential>) -> Result<(), Box<dyn std::error::Error>> {
-    let client = ResourceClient::builder(creds)
-        .retry(RetryOptions::exponential(ExponentialRetryOptions::default()))
-        .build();
+        /// Only shows VMs in these regions. Repeatable or comma separated.
+        #[arg(short, long, value_delimiter = ',')]
+        location: Vec<String>,
 
-    match &args.command {
-        RgCmd::Get { group, sub_id } => {
+        /// Only shows VMs with this tag, as key=value. Repeat to require several.
+        #[arg(short, long, value_parser = parse_tag)]
+        tag: Vec<(String, String)>,
 
-            let sub_id = match sub_id.as_deref() {
-                Some(id) => id,
-                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
-            };
+        /// Only shows running VMs.
+        #[arg(long, conflicts_with = "stopped_only")]
+        running_only: bool,
 
-            let group_name = match group.as_deref() {
-                Some(name) => name,
-                None => store.get_resource_group().ok_or(error::AppError::NoRg)?,
-            };            
+        /// Only shows stopped or deallocated VMs.
+        #[arg(long)]
+        stopped_only: bool,
 
-            let group = client.resource_groups_client()
-                .get(group_name, sub_id)
-                .await?;
+        /// Redraws the list every N seconds (default 5) until Ctrl-C.
+        #[arg(short, long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "5")]
+        watch: Option<u64>,
 
-            display_rg(Output::Single(&group));
-        },
-        RgCmd::List { sub_id } => {
-            let sub_id = match sub_id.as_deref() {
-                Some(id) => id,
-                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
-            };
+        #[arg(long, value_enum)]
+        sort_by: Option<VmSort>,
 
-            let groups: Vec<ResourceGroup> = client.resource_groups_client()
-                .list(sub_id)
-                .into_stream()
-                .try_collect::<Vec<_>>()
-                .await?
-                .into_iter()
-                .flat_map(|groups| groups.value)
-                .collect();
+        /// Reverses the --sort-by order.
+        #[arg(long, requires = "sort_by")]
+        reverse: bool,
 
-            display_rg(Output::Multiple(&groups));
-        }
-    }
+        /// Shows at most this many VMs, without fetching further pages.
+        #[arg(long)]
+        limit: Option<usize>
+    },
+    #[command(after_help = examples::render("vm list-all"))]
+    ListAll {
+        #[arg(short, long)]
+        sub_id: Option<String>,
 
-    Ok(())
-}
+        /// Only shows VMs in these regions. Repeatable or comma separated.
+        #[arg(short, long, value_delimiter = ',')]
+        location: Vec<String>,
 
-async fn send_vm_command(client: &VmClient, vm_names: Option<Vec<String>>, group_name: &str, subscription_id: &str, command: VmCommand) -> Result<(), Box<dyn std::error::Error>> {
-    let mut vm_names = match vm_names {
-        Some(vm_names) => vm_names,
-        None => client.list_vm_names(group_name, subscription_id).await?
-    };
+        /// Only shows VMs with this tag, as key=value. Repeat to require several.
+        #[arg(short, long, value_parser = parse_tag)]
+        tag: Vec<(String, String)>,
 
-    client.command(vm_names.iter(), group_name, subscription_id, command).await?;
+        /// Only shows running VMs.
+        #[arg(long, conflicts_with = "stopped_only")]
+        running_only: bool,
 
-    let total = vm_names.len();
-    let mut completed = 0;
+        /// Only shows stopped or deallocated VMs.
+        #[arg(long)]
+        stopped_only: bool,
 
-    let (prefix, target_state) = match command {
-        VmCommand::Start => ("Started", "VM running"),
-        VmCommand::Stop => ("Stopped", "VM deallocated")
-    };
+        #[arg(long, value_enum)]
+        sort_by: Option<VmSort>,
 
-    let mut spinner = Spinner::new(
-        spinners::Dots,
-        format!("{prefix} 0/{total} virtual machines..."),
-        Color::Blue
-    );
+        /// Reverses the --sort-by order.
+        #[arg(long, requires = "sort_by")]
+        reverse: bool,
 
-    loop {
+        /// Shows at most this many VMs, without fetching further pages.
+        #[arg(long)]
+        limit: Option<usize>
+    },
+    #[command(after_help = examples::render("vm start"))]
+    Start(PowerArgs),
+    #[command(after_help = examples::render("vm stop"))]
+    Stop {
+        #[command(flatten)]
+        args: PowerArgs,
 
-        let done = client
-            .is_complete(vm_names.iter(), group_name, subscription_id, target_state)
-            .await?;
+        /// Powers the VMs off but keeps them allocated, so they keep their
+        /// dynamic IPs and start faster, while compute is still billed.
+        #[arg(long)]
+        no_deallocate: bool
+    },
+    #[command(after_help = examples::render("vm restart"))]
+    Restart(PowerArgs),
+    /// Changes the size of a VM.
+    #[command(after_help = examples::render("vm resize"))]
+    Resize {
+        #[arg(short, long)]
+        name: String,
 
-        completed += done.len();
+        /// The new size, e.g. Standard_D4s_v3.
+        #[arg(long)]
+        size: String,
 
-        spinner.update(
-            spinners::Dots,
-            format!("{prefix} {completed}/{total} virtual machines..."),
-            Color::Blue
-        );
+        #[arg(short, long)]
+        group: Option<String>,
 
-        let temp: Vec<String> = done.iter().map(|s| (*s).clone()).collect();
-        for name in temp.iter() {
-            if let Some(pos) = vm_names.iter().position(|n| n == name) {
-                vm_names.remove(pos);
-            }
-        }
+        #[arg(short, long)]
+        sub_id: Option<String>,
 
-        if vm_names.is_empty() {
-            break;
-        }
-        sleep_until(Instant::now() + Duration::from_secs(2)).await;
-    }
-    spinner.stop();
+        #[command(flatten)]
+        wait: WaitArgs
+    },
+    /// Adds or removes tags on a VM.
+    #[command(after_help = examples::render("vm tag"))]
+    Tag {
+        #[arg(short, long)]
+        name: String,
 
-    let vms = client.list_vms_with_instance_view(
-        group_name,
-        subscription_id
-    ).await?;
+        #[arg(short, long)]
+        group: Option<String>,
 
-    display_vm(Output::Multiple(&vms));
+        #[arg(short, long)]
+        sub_id: Option<String>,
 
-    Ok(())
-}
+        /// Sets a tag, as key=value. Repeatable.
+        #[arg(long, value_parser = parse_tag, required_unless_present = "remove")]
+        add: Vec<(String, String)>,
 
-async fn process_vm_cmd(args: VmArgs, store: &Store, creds: Arc<dyn TokenCredential>) -> Result<(), Box<dyn std::error::Error>> {
-    let client = VmClient::new(creds);
+        /// Removes the tag with this key. Repeatable.
+        #[arg(long)]
+        remove: Vec<String>
+    },
+    /// Shows links to a VM's console screenshot and serial log.
+    BootDiagnostics {
+        #[arg(short, long)]
+        name: String,
 
-    fn get_opt<'a, F>(opt: &'a Option<String>, f: F) -> Result<&'a str, error::AppError>
-    where
-        F: FnOnce() -> Result<&'a str, error::AppError>
-    {
-        match opt.as_deref() {
-            Some(id) => Ok(id),
-            None => f()
-        }
-    }
+        #[arg(short, long)]
+        group: Option<String>,
 
-    match args.command {
-        VmCmd::Get { name, group, sub_id } => {
-            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
-                .ok_or(error::AppError::NoSub))?;
+        #[arg(short, long)]
+        sub_id: Option<String>,
 
-            let group_name = get_opt(&group, || store.get_resource_group()
-                .ok_or(error::AppError::NoRg))?;
+        /// Also saves the screenshot and serial log into this directory.
+        #[arg(long, value_name = "DIR")]
+        download: Option<PathBuf>
+    },
+    /// Shows every status in a VM's instance view, including its agent and extensions.
+    InstanceView {
+        #[arg(short, long)]
+        name: String,
 
-            let vm = client.get_vm_with_instance_view(
-                name.as_str(),
-                group_name,
-                subscription_id
-            ).await?;
+        #[arg(short, long)]
+        group: Option<String>,
 
-            display_vm(Output::Single(&vm));
-        },
-        VmCmd::List { group, sub_id } => {
-            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
-                .ok_or(error::AppError::NoSub))?;
+        #[arg(short, long)]
+        sub_id: Option<String>
+    },
+    /// Lists the image aliases that stand for common marketplace images.
+    Images {
+        /// Shows only the image this alias resolves to.
+        alias: Option<String>
+    },
+    /// Shows only the power state of VMs.
+    #[command(after_help = examples::render("vm status"))]
+    Status {
+        /// VM names, or @FILE to read them from a file, one per line.
+        #[arg(short, long, num_args = 1.., value_delimiter = ',')]
+        names: Option<Vec<String>>,
 
-            let group_name = get_opt(&group, || store.get_resource_group()
-                .ok_or(error::AppError::NoRg))?;
+        #[arg(short, long)]
+        group: Option<String>,
 
-            let vms = client.list_vms_with_instance_view(
-                group_name,
-                subscription_id
-            ).await?;
+        #[arg(short, long)]
+        sub_id: Option<String>
+    },
+    /// Prints common invocations of the vm commands.
+    #[command(hide = true)]
+    Examples
+}
 
-            display_vm(Output::Multiple(&vms));
-        },
-        VmCmd::ListAll { sub_id } => {
-            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
-                .ok_or(error::AppError::NoSub))?;
+#[derive(Args, Debug)]
+struct PowerArgs {
+    /// VM names, or @FILE to read them from a file, one per line.
+    #[arg(short, long, num_args = 1.., value_delimiter = ',')]
+    names: Option<Vec<String>>,
 
-            let vms = client.list_all_vms(subscription_id).await?;
-            display_vm(Output::Multiple(&vms));
-        },
-        VmCmd::Start { names, group, sub_id } => {
+    /// Targets the VMs from the last successful start/stop/restart in each resource group.
+    #[arg(long, conflicts_with = "names")]
+    again: bool,
 
-            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
-                .ok_or(error::AppError::NoSub))?;
+    /// Targets every VM in every resource group of the subscription.
+    #[arg(long, conflicts_with_all = ["names", "group", "again"], requires = "yes")]
+    all_groups: bool,
 
-            let group_name = get_opt(&group, || store.get_resource_group()
-                .ok_or(error::AppError::NoRg))?;
+    /// Resource groups of the VMs. Repeatable or comma separated.
+    #[arg(short, long, num_args = 1.., value_delimiter = ',')]
+    group: Option<Vec<String>>,
 
-            send_vm_command(
-                &client,
-                names,
-                group_name,
-                subscription_id,
-                VmCommand::Start
-            ).await?;
-        },
-        VmCmd::Stop { names, group, sub_id } => {
-            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
-                .ok_or(error::AppError::NoSub))?;
+    /// Targets every VM in the resource groups except these. Repeatable or comma separated.
+    #[arg(short = 'x', long, num_args = 1.., value_delimiter = ',', conflicts_with = "names")]
+    exclude: Vec<String>,
 
-            let group_name = get_opt(&group, || store.get_resource_group()
-                .ok_or(error::AppError::NoRg))?;
+    /// Skips --names, --again and --exclude VMs that aren't in the targeted resource groups.
+    #[arg(long)]
+    ignore_missing: bool,
 
-            send_vm_command(
-                &client,
-                names,
-                group_name,
-                subscription_id,
-                VmCommand::Stop
-            ).await?;
-        }
-    }
+    #[arg(short, long)]
+    sub_id: Option<String>,
+
+    /// Lists the VMs that would be affected without changing them.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Seconds between checks on the VMs' power state.
+    #[arg(long, value_name = "SECONDS", default_value_t = 2)]
+    poll_interval: u64,
+
+    /// Backs off from --poll-interval to checking this rarely on long operations.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    max_poll_interval: u64,
+
+    /// Skips the confirmation prompt, and stops every VM rather than asking which to stop.
+    #[arg(short, long, visible_alias = "force")]
+    yes: bool,
+
+    #[command(flatten)]
+    wait: WaitArgs
+}
+
+/// Whether to wait for a change to a VM to finish.
+#[derive(Args, Debug)]
+struct WaitArgs {
+    /// Returns as soon as Azure has accepted the request, without waiting for it to finish.
+    #[arg(long, overrides_with = "wait")]
+    no_wait: bool,
+
+    /// Waits for the change to finish before returning. This is the default.
+    #[arg(long, overrides_with = "no_wait")]
+    wait: bool
+}
+
+#[derive(Args, Debug)]
+struct RgArgs {
+    #[command(subcommand)]
+    command: RgCmd
+}
+
+#[derive(Subcommand, Debug)]
+enum RgCmd {
+    Get {
+        #[arg(short, long)]
+        group: Option<String>,
+
+        #[arg(short, long)]
+        sub_id: Option<String>,
+
+        /// Also counts the group's VMs and how many are running.
+        #[arg(long)]
+        with_vms: bool,
+
+        /// Writes the group's ARM template to this file instead of displaying the group.
+        #[arg(long, value_name = "FILE", conflicts_with = "with_vms")]
+        export: Option<PathBuf>,
+
+        /// Keeps the current values as parameter defaults in the exported template.
+        #[arg(long, requires = "export")]
+        include_parameters: bool
+    },
+    List {
+        #[arg(short, long)]
+        sub_id: Option<String>,
+
+        /// Lists the resource groups of every subscription you can access.
+        #[arg(long, conflicts_with = "sub_id")]
+        all_subs: bool,
+
+        /// Only shows groups in these regions. Repeatable or comma separated.
+        #[arg(short, long, value_delimiter = ',')]
+        location: Vec<String>,
+
+        /// Only shows groups with this tag, as key=value. Repeat to require several.
+        #[arg(short, long, value_parser = parse_tag)]
+        tag: Vec<(String, String)>
+    }
+}
+
+#[derive(Args, Debug)]
+struct SubArgs {
+    #[command(subcommand)]
+    command: SubCmd
+}
+
+#[derive(Subcommand, Debug)]
+enum SubCmd {
+    /// Displays information about about a subscription.
+    Get {
+        /// Displays information about the specified subscription (ID or display name),
+        /// else displays information about the currently selected subscription.
+        #[arg(short, long)]
+        id: Option<String>,
+
+        /// Also shows the month-to-date cost from Cost Management.
+        #[arg(long)]
+        with_spending: bool
+    },
+    /// Displays information about all subscriptions.
+    List
+}
+
+/// Parses a `key=value` tag argument.
+fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("invalid tag '{tag}', expected key=value"))
+    }
+}
+
+/// Parses an RFC 3339 timestamp such as `2024-05-01T00:00:00Z`.
+fn parse_timestamp(value: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map_err(|e| format!("invalid timestamp '{value}' ({e}), expected RFC 3339 such as 2024-05-01T00:00:00Z"))
+}
+
+/// Replaces each `@path` entry with the names listed in that file, one per
+/// line, skipping blank lines and `#` comments.
+fn expand_names(names: Vec<String>) -> Result<Vec<String>, error::AppError> {
+    let mut expanded = Vec::with_capacity(names.len());
+    for name in names {
+        let Some(path) = name.strip_prefix('@') else {
+            expanded.push(name);
+            continue;
+        };
+
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| error::AppError::NamesFile(path.to_owned(), e))?;
+
+        expanded.extend(contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned));
+    }
+    Ok(expanded)
+}
+
+/// Checks for the `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` shape of a subscription ID.
+fn is_guid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5 && groups.iter()
+        .zip([8, 4, 4, 4, 12])
+        .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn credential(auth: AuthMethod) -> Result<Arc<dyn TokenCredential>, error::AppError> {
+    let creds: Arc<dyn TokenCredential> = match auth {
+        AuthMethod::Cli => Arc::new(AzureCliCredential::new()),
+        AuthMethod::Default => Arc::new(DefaultAzureCredential::create(TokenCredentialOptions::default())?),
+        AuthMethod::ManagedIdentity => Arc::new(ImdsManagedIdentityCredential::default()),
+        AuthMethod::Env => Arc::new(EnvironmentCredential::create(TokenCredentialOptions::default())?)
+    };
+
+    // Every client shares this credential, so cache tokens to avoid
+    // shelling out to `az` for each request.
+    Ok(Arc::new(CachedCredential::new(creds)))
+}
+
+fn subscription_client(creds: Arc<dyn TokenCredential>) -> SubscriptionClient {
+    SubscriptionClient::builder(creds)
+        .retry(retry::options())
+        .transport(api_version::transport(Service::Subscription))
+        .build()
+}
+
+/// Queries Cost Management for the actual cost of a subscription so far this month.
+async fn month_to_date_cost(creds: Arc<dyn TokenCredential>, sub_id: &str) -> Result<Spending, error::AppError> {
+    let client = CostClient::builder(creds)
+        .retry(retry::options())
+        .transport(api_version::transport(Service::Cost))
+        .build();
+
+    let query: QueryDefinition = serde_json::from_value(json!({
+        "type": "ActualCost",
+        "timeframe": "MonthToDate",
+        "dataset": {
+            "granularity": "None",
+            "aggregation": {
+                "totalCost": { "name": "Cost", "function": "Sum" }
+            }
+        }
+    }))?;
+
+    info!("Querying month-to-date cost of {sub_id}");
+    let result = timings::time("query cost", client.query_client()
+        .usage(format!("subscriptions/{sub_id}"), query))
+        .await?;
+
+    // The columns are named in the response rather than in the model, so
+    // look them up before reading the single aggregated row.
+    let result = serde_json::to_value(result)?;
+    let properties = &result["properties"];
+    let column = |name: &str| properties["columns"].as_array()
+        .and_then(|columns| columns.iter().position(|column| column["name"].as_str().is_some_and(|column| column.eq_ignore_ascii_case(name))));
+
+    let (Some(cost), Some(currency)) = (column("totalCost").or_else(|| column("Cost")), column("Currency")) else {
+        return Err(error::AppError::OperationFailed("Cost Management returned no cost column".to_owned()));
+    };
+
+    let row = &properties["rows"][0];
+    Ok(Spending {
+        month_to_date: row[cost].as_f64().unwrap_or_default(),
+        currency: row[currency].as_str().unwrap_or_default().to_owned()
+    })
+}
+
+fn vault_client(creds: Arc<dyn TokenCredential>) -> VaultClient {
+    VaultClient::builder(creds)
+        .retry(retry::options())
+        .transport(api_version::transport(Service::Vault))
+        .build()
+}
+
+fn resource_client(creds: Arc<dyn TokenCredential>) -> ResourceClient {
+    ResourceClient::builder(creds)
+        .retry(retry::options())
+        .transport(api_version::transport(Service::Resources))
+        .build()
+}
+
+/// Exports the ARM template of a resource group. Fails rather than returning
+/// a partial template when some resources can't be exported.
+async fn export_template(client: &ResourceClient, group_name: &str, sub_id: &str, include_parameters: bool) -> Result<serde_json::Value, error::AppError> {
+    let request = ExportTemplateRequest {
+        resources: vec!["*".to_owned()],
+        options: include_parameters.then(|| "IncludeParameterDefaultValue".to_owned())
+    };
+
+    info!("Exporting template of resource group {group_name}");
+    let result = timings::time("export template", client.resource_groups_client()
+        .export_template(group_name, request, sub_id))
+        .await
+        .map_err(error::AppError::or_not_found("resource group", group_name))?;
+
+    let failed = |reason: String| error::AppError::ExportFailed { group: group_name.to_owned(), reason };
+    if let Some(error) = result.error {
+        let mut reasons: Vec<String> = error.details.iter()
+            .filter_map(|detail| detail.message.clone())
+            .collect();
+        if reasons.is_empty() {
+            reasons.extend(error.message);
+        }
+        return Err(failed(reasons.join("; ")));
+    }
+
+    let template = result.template.ok_or_else(|| failed("Azure returned no template".to_owned()))?;
+    let empty = template.get("resources")
+        .and_then(|resources| resources.as_array())
+        .map_or(true, |resources| resources.is_empty());
+    if empty {
+        return Err(failed("the group has no resources".to_owned()));
+    }
+    Ok(template)
+}
+
+async fn list_subscriptions(client: &SubscriptionClient) -> Result<Vec<Subscription>, error::AppError> {
+    info!("Listing subscriptions");
+    let subs: Vec<Subscription> = timings::time("list subscriptions", client.subscriptions_client()
+        .list()
+        .into_stream()
+        .try_collect::<Vec<_>>())
+        .await?
+        .into_iter()
+        .flat_map(|subs| subs.value)
+        .collect();
+
+    Ok(subs)
+}
+
+async fn list_resource_groups(client: &ResourceClient, subscription_id: &str) -> Result<Vec<ResourceGroup>, error::AppError> {
+    info!("Listing resource groups in {subscription_id}");
+    let groups: Vec<ResourceGroup> = timings::time("list resource groups", client.resource_groups_client()
+        .list(subscription_id)
+        .into_stream()
+        .try_collect::<Vec<_>>())
+        .await?
+        .into_iter()
+        .flat_map(|groups| groups.value)
+        .collect();
+
+    Ok(groups)
+}
+
+/// Resolves a subscription GUID or display name to a subscription ID.
+async fn resolve_subscription_id(client: &SubscriptionClient, value: &str) -> Result<String, error::AppError> {
+    if is_guid(value) {
+        return Ok(value.to_owned());
+    }
+
+    let ids: Vec<String> = list_subscriptions(client)
+        .await?
+        .into_iter()
+        .filter(|sub| sub.display_name.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(value)))
+        .filter_map(|sub| sub.subscription_id)
+        .collect();
+
+    match ids.len() {
+        0 => Err(error::AppError::UnknownSubscription(value.to_owned())),
+        1 => Ok(ids.into_iter().next().unwrap_or_default()),
+        _ => Err(error::AppError::AmbiguousSubscription(value.to_owned(), ids))
+    }
+}
+
+/// The API versions saved in the store, with --set-api-version and
+/// --api-version applied on top.
+fn api_versions(cli: &Cli, store: &Store) -> BTreeMap<Service, String> {
+    let mut versions: BTreeMap<Service, String> = store.get_api_versions()
+        .into_iter()
+        .filter_map(|(service, version)| match Service::from_str(service, true) {
+            Ok(service) => Some((service, version.to_owned())),
+            Err(_) => {
+                warn!("Ignoring the stored API version of unknown service '{service}'");
+                None
+            }
+        })
+        .collect();
+
+    for (service, version) in cli.set_api_version.iter().chain(&cli.api_version) {
+        match version.is_empty() {
+            true => versions.remove(service),
+            false => versions.insert(*service, version.clone())
+        };
+    }
+    versions
+}
+
+async fn handle_globals(cli: &Cli, store: &mut Store, creds: Arc<dyn TokenCredential>) -> Result<(), error::AppError> {
+    if let Some(sub) = cli.set_sub.as_deref() {
+        let client = subscription_client(creds.clone());
+        let sub_id = resolve_subscription_id(&client, sub).await?;
+
+        if !cli.no_validate {
+            debug!("Validating subscription: {sub_id}");
+            client.subscriptions_client()
+                .get(sub_id.as_str())
+                .await
+                .map_err(|e| error::AppError::Validation(format!("subscription {sub_id}"), e))?;
+        }
+
+        debug!("Setting subscription to: {sub_id}");
+        store.set_subscription_id(&sub_id);
+    }
+
+    if let Some(rg) = cli.set_rg.as_deref() {
+        // Azure matches group names in any case, so save the name as Azure
+        // spells it rather than as typed.
+        let rg = match cli.no_validate {
+            true => rg.to_owned(),
+            false => {
+                let sub_id = store.get_subscription_id().ok_or(error::AppError::NoSub)?;
+
+                debug!("Validating resource group: {rg}");
+                resource_client(creds.clone())
+                    .resource_groups_client()
+                    .get(rg, sub_id)
+                    .await
+                    .map_err(|e| error::AppError::Validation(format!("resource group {rg}"), e))?
+                    .name
+                    .unwrap_or_else(|| rg.to_owned())
+            }
+        };
+
+        debug!("Setting resource group to: {rg}");
+        store.set_resource_group(&rg);
+    }
+
+    if let Some(vault) = cli.set_vault.as_deref() {
+        debug!("Setting vault to: {vault}");
+        store.set_vault_name(vault);
+    }
+
+    if let Some(vault_rg) = cli.set_vault_rg.as_deref() {
+        debug!("Setting vault resource group to: {vault_rg}");
+        store.set_vault_resource_group(vault_rg);
+    }
+
+    // The vault can only be checked once both its name and group are known,
+    // which may take two invocations.
+    let vault_changed = cli.set_vault.is_some() || cli.set_vault_rg.is_some();
+    if let (true, false, Some(vault), Some(vault_rg)) = (vault_changed, cli.no_validate, store.get_vault_name(), store.get_vault_resource_group()) {
+        let sub_id = store.get_subscription_id().ok_or(error::AppError::NoSub)?;
+
+        debug!("Validating vault: {vault}");
+        let vault = vault_client(creds)
+            .vaults_client()
+            .get(sub_id, vault_rg, vault)
+            .await
+            .map_err(|e| error::AppError::Validation(format!("vault {vault} in {vault_rg}, see `vault list` for the available ones"), e))?;
+
+        // Save the name as Azure spells it, as with --set-rg.
+        if let Some(name) = serde_json::to_value(&vault)?["name"].as_str() {
+            store.set_vault_name(name);
+        }
+    }
+
+    for (service, version) in &cli.set_api_version {
+        debug!("Setting {} API version to: {version}", service.name());
+        store.set_api_version(service.name(), version);
+    }
+
+    if cli.set_sub.is_some() || cli.set_rg.is_some() || cli.set_vault.is_some() || cli.set_vault_rg.is_some() || !cli.set_api_version.is_empty() {
+        debug!("Saving store file");
+        store.save().await?;
+    }
+    Ok(())
+}
+
+async fn process_profile_cmd(args: ProfileArgs, store: &mut Store) -> Result<(), error::AppError> {
+    match &args.command {
+        ProfileCmd::Create { name } => {
+            store.create_profile(name)?;
+            store.save().await?;
+        },
+        ProfileCmd::Switch { name } => {
+            store.switch_profile(name)?;
+            store.save().await?;
+        },
+        ProfileCmd::List => {
+            let active = store.get_active_profile();
+            for name in store.list_profiles() {
+                let marker = if name == active { "*" } else { " " };
+                println!("{marker} {name}");
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn process_config_cmd(args: ConfigArgs, store: &mut Store, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    match &args.command {
+        ConfigCmd::Show => {
+            let config = Config {
+                profile: store.get_active_profile(),
+                subscription_id: store.get_subscription_id(),
+                resource_group: store.get_resource_group(),
+                vault_name: store.get_vault_name(),
+                vault_resource_group: store.get_vault_resource_group(),
+                api_versions: store.get_api_versions()
+            };
+
+            display_config(&config, opts)?;
+        },
+        ConfigCmd::Clear => {
+            store.clear();
+            store.save().await?;
+        }
+    }
+    Ok(())
+}
+
+async fn process_sub_cmd(args: SubArgs, store: &Store, creds: Arc<dyn TokenCredential>, opts: &DisplayOptions) -> Result<(), error::AppError> {
+
+    let client = subscription_client(creds.clone());
+
+    match &args.command {
+        SubCmd::Get { id, with_spending } => {
+            let sub_id = match id.as_deref() {
+                Some(id) => resolve_subscription_id(&client, id).await?,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?.to_owned(),
+            };
+
+            info!("Getting subscription {sub_id}");
+            let sub = timings::time("get subscription", client.subscriptions_client()
+                .get(sub_id.as_str()))
+                .await
+                .map_err(error::AppError::or_not_found("subscription", &sub_id))?;
+
+            if !with_spending {
+                display_sub(Output::Single(&sub), store.get_subscription_id(), opts)?;
+                return Ok(());
+            }
+
+            let spending = month_to_date_cost(creds, &sub_id).await?;
+            display_sub_with_spending(&sub, store.get_subscription_id(), &spending, opts)?;
+        },
+        SubCmd::List => {
+            let mut subs = list_subscriptions(&client).await?;
+            subs.sort_by_cached_key(|sub| sub.display_name.as_deref().unwrap_or_default().to_lowercase());
+
+            display_sub(Output::Multiple(&subs), store.get_subscription_id(), opts)?;
+        }
+    }
+    Ok(())
+}
+
+async fn process_rg_cmd(args: RgArgs, store: &Store, creds: Arc<dyn TokenCredential>, concurrency: usize, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let client = resource_client(creds.clone());
+
+    match &args.command {
+        RgCmd::Get { group, sub_id, with_vms, export, include_parameters } => {
+
+            let sub_id = match sub_id.as_deref() {
+                Some(id) => id,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
+            };
+
+            let group_name = match group.as_deref() {
+                Some(name) => name,
+                None => store.get_resource_group().ok_or(error::AppError::NoRg)?,
+            };
+
+            debug!("Resolved subscription {sub_id}, resource group {group_name}");
+            if let Some(path) = export {
+                let template = export_template(&client, group_name, sub_id, *include_parameters).await?;
+                std::fs::write(path, serde_json::to_string_pretty(&template)?)?;
+                if !opts.quiet {
+                    println!("Exported {group_name} to {}", path.display());
+                }
+                return Ok(());
+            }
+
+            info!("Getting resource group {group_name}");
+            let group = timings::time("get resource group", client.resource_groups_client()
+                .get(group_name, sub_id))
+                .await
+                .map_err(error::AppError::or_not_found("resource group", group_name))?;
+
+            if !with_vms {
+                display_rg(Output::Single(&group), opts)?;
+                return Ok(());
+            }
+
+            let vms = VmClient::new(creds)
+                .with_concurrency(concurrency)
+                .list_vms_with_instance_view(group_name, sub_id, &VmFilter::default())
+                .await?;
+
+            let running = vms.iter()
+                .filter_map(|vm| vm.properties.as_ref()?.instance_view.as_ref())
+                .filter(|view| power_state(view) == "VM running")
+                .count();
+
+            display_rg_with_vms(&group, &VmCounts { total: vms.len(), running }, opts)?;
+        },
+        RgCmd::List { all_subs: true, location, tag, .. } => {
+            let filter = VmFilter { locations: location.clone(), tags: tag.clone(), limit: None, state: None };
+            let subs = list_subscriptions(&subscription_client(creds))
+                .await?
+                .into_iter()
+                .filter_map(|sub| {
+                    let sub_id = sub.subscription_id?;
+                    Some((sub.display_name.unwrap_or_else(|| sub_id.clone()), sub_id))
+                });
+
+            let mut groups: Vec<SubscriptionGroup> = stream::iter(subs)
+                .map(|(name, sub_id)| {
+                    let client = &client;
+                    async move {
+                        let groups = list_resource_groups(client, &sub_id).await?;
+                        Ok::<_, error::AppError>(groups.into_iter().map(move |group| SubscriptionGroup {
+                            subscription: name.clone(),
+                            group
+                        }))
+                    }
+                })
+                .buffer_unordered(concurrency.max(1))
+                .try_collect::<Vec<_>>()
+                .await?
+                .into_iter()
+                .flatten()
+                .filter(|sub_group| filter.matches_resource(&sub_group.group.location, sub_group.group.tags.as_ref()))
+                .collect();
+
+            if groups.is_empty() && !filter.is_empty() {
+                return Err(error::AppError::NoMatchingGroups);
+            }
+
+            groups.sort_by_cached_key(|sub_group| (
+                sub_group.subscription.to_lowercase(),
+                sub_group.group.name.as_deref().unwrap_or_default().to_lowercase()
+            ));
+
+            display_rg_by_sub(Output::Multiple(&groups), opts)?;
+        },
+        RgCmd::List { sub_id, location, tag, .. } => {
+            let sub_id = match sub_id.as_deref() {
+                Some(id) => id,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
+            };
+
+            let filter = VmFilter { locations: location.clone(), tags: tag.clone(), limit: None, state: None };
+            let mut groups = list_resource_groups(&client, sub_id).await?;
+            groups.retain(|group| filter.matches_resource(&group.location, group.tags.as_ref()));
+
+            if groups.is_empty() && !filter.is_empty() {
+                return Err(error::AppError::NoMatchingGroups);
+            }
+
+            display_rg(Output::Multiple(&groups), opts)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that output may go to `path` without touching the file, which is
+/// only opened once there is something to write. Unless `append` is set the
+/// first write replaces whatever `overwrite` allowed to be there.
+fn output_file(path: &Path, overwrite: bool, append: bool) -> Result<OutputFile, error::AppError> {
+    if path.exists() && !overwrite && !append {
+        return Err(error::AppError::OutputFileExists(path.to_owned()));
+    }
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::metadata(parent)?;
+    }
+    Ok(OutputFile::new(path.to_owned(), !append))
+}
+
+/// Saves the blob behind a SAS URL to `path`.
+async fn download_file(url: &str, path: &Path) -> Result<(), error::AppError> {
+    let url = Url::parse(url)
+        .map_err(|e| error::AppError::OperationFailed(format!("Invalid download URL: {e}")))?;
+
+    info!("Downloading {}", path.display());
+    let response = azure_core::new_http_client()
+        .execute_request(&Request::new(url, Method::Get))
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(error::AppError::OperationFailed(format!("Download of {} returned {status:?}", path.display())));
+    }
+
+    let contents = response.into_body().collect().await?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, contents).await?;
+    Ok(())
+}
+
+/// Reads a line of input from the user. Returns `None` rather than blocking
+/// when stdin isn't a terminal.
+fn prompt(question: &str) -> Result<Option<String>, error::AppError> {
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    print!("{question} ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(Some(answer.trim().to_owned()))
+}
+
+/// Lets the user choose which of the VMs in `groups` to act on, showing each
+/// with its current power state. Unchosen VMs are removed from `groups`.
+async fn pick_vms(client: &VmClient, groups: &mut [(String, Vec<String>)], subscription_id: &str, color: bool) -> Result<(), error::AppError> {
+    let mut items = Vec::new();
+    for (group_name, vm_names) in groups.iter() {
+        let statuses = client.get_power_states(vm_names.iter(), group_name, subscription_id).await?;
+        for status in statuses {
+            let state = match (color, status.status.as_str()) {
+                (false, state) => state.normal(),
+                (true, state @ "VM deallocated") => state.black().on_red(),
+                (true, state @ "VM stopped") => state.black().on_magenta(),
+                (true, state @ ("VM deallocating" | "VM stopping" | "VM starting")) => state.black().on_yellow(),
+                (true, state @ "VM running") => state.black().on_green(),
+                (true, state) => state.normal()
+            };
+            items.push(format!("{} ({group_name}) {state}", status.name));
+        }
+    }
+
+    let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select the virtual machines to stop")
+        .items(&items)
+        .interact_opt()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+        .unwrap_or_default();
+
+    let mut index = 0;
+    for (_, vm_names) in groups.iter_mut() {
+        vm_names.retain(|_| {
+            let keep = chosen.contains(&index);
+            index += 1;
+            keep
+        });
+    }
+    Ok(())
+}
+
+/// Resolves the target groups and VMs of a start/stop/restart, runs it, and
+/// remembers the VMs for `--again` once it succeeds.
+async fn run_power_cmd(client: &VmClient, args: &PowerArgs, store: &mut Store, creds: Arc<dyn TokenCredential>, command: VmCommand, timeout: Duration, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let subscription_id = match args.sub_id.as_deref() {
+        Some(id) => id.to_owned(),
+        None => store.get_subscription_id().ok_or(error::AppError::NoSub)?.to_owned()
+    };
+
+    let targets: Vec<(String, Option<Vec<String>>)> = if args.all_groups {
+        list_resource_groups(&resource_client(creds), &subscription_id)
+            .await?
+            .into_iter()
+            .filter_map(|group| group.name)
+            .map(|group| (group, None))
+            .collect()
+    } else {
+        let mut group_names = match args.group.as_deref() {
+            Some(names) => names.to_vec(),
+            None => vec![store.get_resource_group().ok_or(error::AppError::NoRg)?.to_owned()]
+        };
+
+        // Group names are case-insensitive, so `-g App,app` is one group.
+        let mut seen = HashSet::new();
+        group_names.retain(|group_name| seen.insert(group_name.to_lowercase()));
+
+        if args.again {
+            let targets = group_names.into_iter()
+                .map(|group_name| {
+                    let names = store.get_last_vm_names(&group_name)
+                        .ok_or_else(|| error::AppError::NoLastVms(group_name.clone()))?;
+                    Ok((group_name, Some(names.to_vec())))
+                })
+                .collect::<Result<_, error::AppError>>()?;
+            validate_names(client, targets, &subscription_id, args.ignore_missing).await?
+        } else {
+            let names = args.names.clone().map(expand_names).transpose()?;
+            match (names, group_names.as_slice()) {
+                (Some(names), [_, _, ..]) => assign_to_groups(client, names, group_names, &subscription_id, args.ignore_missing).await?,
+                (names, _) => {
+                    let targets = group_names.into_iter().map(|group_name| (group_name, names.clone())).collect();
+                    validate_names(client, targets, &subscription_id, args.ignore_missing).await?
+                }
+            }
+        }
+    };
+
+    debug!("Resolved subscription {subscription_id}, targets {targets:?}");
+    let Some(groups) = resolve_targets(client, args, targets, &subscription_id, command, opts).await? else {
+        return Ok(());
+    };
+
+    let results = if args.wait.no_wait {
+        let results = dispatch_vm_command(client, groups, &subscription_id, command).await;
+        report_dispatched(&results, command, opts)?;
+        results
+    } else {
+        // Keep machine-readable output free of progress chatter.
+        let prefix = command.past_tense();
+        let mut spinner = SpinnerGuard::new(format!("{prefix} 0 virtual machines..."), opts);
+
+        let started = Instant::now();
+        let poll = PollInterval {
+            initial: Duration::from_secs(args.poll_interval.max(1)),
+            max: Duration::from_secs(args.max_poll_interval)
+        };
+        let results = send_vm_command(client, groups, &subscription_id, command, timeout, poll, |completed, total| {
+            spinner.update(format!("{prefix} {completed}/{total} virtual machines... ({}s)", started.elapsed().as_secs()));
+        }).await?;
+        drop(spinner);
+
+        report_power_results(client, &results, &subscription_id, command, opts).await?;
+        results
+    };
+
+    let mut groups: Vec<(&str, Vec<String>)> = Vec::new();
+    for result in &results {
+        match groups.iter_mut().find(|(group_name, _)| group_name.eq_ignore_ascii_case(&result.group)) {
+            Some((_, vm_names)) => vm_names.push(result.name.clone()),
+            None => groups.push((&result.group, vec![result.name.clone()]))
+        }
+    }
+
+    let (failed, timed_out): (Vec<&VmOperationResult>, Vec<&VmOperationResult>) = results.iter()
+        .filter(|result| !result.succeeded)
+        .partition(|result| result.final_state != "Timed out");
+
+    if !failed.is_empty() || !timed_out.is_empty() {
+        return Err(error::AppError::Incomplete {
+            failed: failed.into_iter().map(|result| result.name.clone()).collect(),
+            timed_out: timed_out.into_iter().map(|result| result.name.clone()).collect()
+        });
+    }
+
+    for (group_name, vm_names) in groups {
+        store.set_last_vm_names(group_name, &vm_names);
+    }
+    store.save().await?;
+    Ok(())
+}
+
+/// Prints how many VMs accepted a `--no-wait` start/stop/restart, or the
+/// outcome of each VM for formats other than tables.
+fn report_dispatched(results: &[VmOperationResult], command: VmCommand, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    if opts.quiet {
+        return Ok(());
+    }
+
+    if opts.format != Format::Table {
+        display_vm_results(Output::Multiple(results), opts)?;
+        return Ok(());
+    }
+
+    let accepted = results.iter().filter(|result| result.succeeded).count();
+    println!(
+        "{} accepted for {accepted}/{} virtual machines, not waiting for them to finish",
+        command.action(),
+        results.len()
+    );
+    Ok(())
+}
+
+/// Prints how a start/stop/restart went: a summary per resource group and the
+/// resulting VMs for tables, or the outcome of each VM for other formats.
+async fn report_power_results(client: &VmClient, results: &[VmOperationResult], subscription_id: &str, command: VmCommand, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    if opts.quiet {
+        return Ok(());
+    }
+
+    if opts.format != Format::Table {
+        display_vm_results(Output::Multiple(results), opts)?;
+        return Ok(());
+    }
+
+    let mut outcomes: Vec<(&str, Outcome)> = Vec::new();
+    for result in results {
+        let index = match outcomes.iter().position(|(group_name, _)| group_name.eq_ignore_ascii_case(&result.group)) {
+            Some(index) => index,
+            None => {
+                outcomes.push((&result.group, Outcome::default()));
+                outcomes.len() - 1
+            }
+        };
+
+        let outcome = &mut outcomes[index].1;
+        match result {
+            VmOperationResult { skipped: true, .. } => outcome.skipped += 1,
+            VmOperationResult { succeeded: true, .. } => outcome.succeeded += 1,
+            VmOperationResult { final_state, .. } if final_state == "Timed out" => outcome.timed_out += 1,
+            _ => outcome.failed += 1
+        }
+    }
+
+    match outcomes.as_slice() {
+        [(_, outcome)] => println!("{}", outcome.summary(command.target_state())),
+        outcomes => for (group_name, outcome) in outcomes {
+            println!("{group_name}: {}", outcome.summary(command.target_state()));
+        }
+    }
+
+    let mut vms = Vec::new();
+    for (group_name, _) in outcomes.iter() {
+        vms.extend(client.list_vms_with_instance_view(
+            group_name,
+            subscription_id,
+            &VmFilter::default()
+        ).await?);
+    }
+
+    display_vm(Output::Multiple(&vms), opts)?;
+    Ok(())
+}
+
+/// Works out which of `group_names` holds each named VM, so one list of
+/// names can span several groups.
+async fn assign_to_groups(client: &VmClient, mut names: Vec<String>, group_names: Vec<String>, subscription_id: &str, ignore_missing: bool) -> Result<Vec<(String, Option<Vec<String>>)>, error::AppError> {
+    let mut targets = Vec::with_capacity(group_names.len());
+    for group_name in group_names {
+        let in_group = client.list_vm_names(&group_name, subscription_id).await?;
+        let (found, rest): (Vec<String>, Vec<String>) = names.into_iter()
+            .partition(|name| in_group.iter().any(|vm| vm.eq_ignore_ascii_case(name)));
+
+        names = rest;
+        if !found.is_empty() {
+            targets.push((group_name, Some(found)));
+        }
+    }
+
+    report_missing(names, ignore_missing)?;
+    Ok(targets)
+}
+
+/// Checks that the VMs named for each group exist in it, so a typo is
+/// reported up front rather than polled until the timeout.
+async fn validate_names(client: &VmClient, targets: Vec<(String, Option<Vec<String>>)>, subscription_id: &str, ignore_missing: bool) -> Result<Vec<(String, Option<Vec<String>>)>, error::AppError> {
+    let mut validated = Vec::with_capacity(targets.len());
+    let mut missing = Vec::new();
+    for (group_name, names) in targets {
+        let Some(names) = names else {
+            validated.push((group_name, None));
+            continue;
+        };
+
+        let in_group = client.list_vm_names(&group_name, subscription_id).await?;
+        let (found, unknown): (Vec<String>, Vec<String>) = names.into_iter()
+            .partition(|name| in_group.iter().any(|vm| vm.eq_ignore_ascii_case(name)));
+
+        missing.extend(unknown);
+        validated.push((group_name, Some(found)));
+    }
+
+    report_missing(missing, ignore_missing)?;
+    Ok(validated)
+}
+
+/// Fails on VM names that weren't found, or just warns with `ignore_missing`.
+fn report_missing(missing: Vec<String>, ignore_missing: bool) -> Result<(), error::AppError> {
+    match (missing.is_empty(), ignore_missing) {
+        (true, _) => Ok(()),
+        (false, true) => {
+            eprintln!("Skipping VMs that were not found: {}", missing.join(", "));
+            Ok(())
+        },
+        (false, false) => Err(error::AppError::UnknownVms(missing))
+    }
+}
+
+/// How the VMs of one resource group fared in a start/stop/restart.
+#[derive(Debug, Default)]
+struct Outcome {
+    succeeded: usize,
+    failed: usize,
+    timed_out: usize,
+    skipped: usize
+}
+
+impl Outcome {
+    /// Summarizes the counts, e.g. `3 succeeded, 1 already running`.
+    fn summary(&self, target_state: &str) -> String {
+        let mut summary = vec![format!("{} succeeded", self.succeeded)];
+        if self.failed > 0 {
+            summary.push(format!("{} failed", self.failed));
+        }
+        if self.timed_out > 0 {
+            summary.push(format!("{} timed out", self.timed_out));
+        }
+        if self.skipped > 0 {
+            let state = target_state.trim_start_matches("VM ");
+            summary.push(format!("{} already {state}", self.skipped));
+        }
+        summary.join(", ")
+    }
+}
+
+/// Lists the VMs of each group that has no names given, then shows them for
+/// `--dry-run` or lets the user pick which to stop. Returns the VMs to act on
+/// per group, or `None` when nothing should be changed.
+async fn resolve_targets(client: &VmClient, args: &PowerArgs, targets: Vec<(String, Option<Vec<String>>)>, subscription_id: &str, command: VmCommand, opts: &DisplayOptions) -> Result<Option<Vec<(String, Vec<String>)>>, error::AppError> {
+    let explicit = targets.iter().all(|(_, names)| names.is_some());
+    let mut groups = Vec::with_capacity(targets.len());
+    for (group_name, names) in targets {
+        let vm_names = match names {
+            Some(vm_names) => vm_names,
+            None => client.list_vm_names(&group_name, subscription_id).await?
+        };
+        groups.push((group_name, vm_names));
+    }
+
+    if !args.exclude.is_empty() {
+        let is_excluded = |name: &str| args.exclude.iter().any(|excluded| excluded.eq_ignore_ascii_case(name));
+        let missing: Vec<String> = args.exclude.iter()
+            .filter(|excluded| !groups.iter().flat_map(|(_, vm_names)| vm_names).any(|name| name.eq_ignore_ascii_case(excluded)))
+            .cloned()
+            .collect();
+
+        if !missing.is_empty() && !args.ignore_missing {
+            return Err(error::AppError::ExcludedNotFound(missing));
+        }
+        for (_, vm_names) in groups.iter_mut() {
+            vm_names.retain(|name| !is_excluded(name));
+        }
+    }
+
+    if args.dry_run {
+        let actions: Vec<VmAction> = groups.iter()
+            .flat_map(|(_, vm_names)| vm_names)
+            .map(|name| VmAction { name: name.clone(), action: command.action().to_owned() })
+            .collect();
+
+        display_vm_actions(Output::Multiple(&actions), opts)?;
+        return Ok(None);
+    }
+
+    // Stopping takes VMs down, and deallocating releases public IPs and
+    // ephemeral disks, so rather than stopping every VM in the group let the
+    // user pick which ones to stop.
+    if matches!(command, VmCommand::Stop | VmCommand::PowerOff) && !explicit && !args.yes {
+        if !io::stdin().is_terminal() {
+            return Err(error::AppError::ConfirmationRequired);
+        }
+
+        pick_vms(client, &mut groups, subscription_id, opts.color).await?;
+        if groups.iter().all(|(_, vm_names)| vm_names.is_empty()) {
+            println!("Aborted");
+            return Ok(None);
+        }
+    }
+    Ok(Some(groups))
+}
+
+/// Drops repeats of a VM name within each group, ignoring case.
+fn dedupe_names(groups: &mut [(String, Vec<String>)]) {
+    for (_, vm_names) in groups.iter_mut() {
+        let mut seen = HashSet::new();
+        vm_names.retain(|name| seen.insert(name.to_lowercase()));
+    }
+}
+
+/// Sends `command` to the VMs in `groups` for `--no-wait`, without checking
+/// their state before or after. VMs whose request was accepted are reported
+/// as succeeded with an `Accepted` state.
+async fn dispatch_vm_command(client: &impl VmOperations, mut groups: Vec<(String, Vec<String>)>, subscription_id: &str, command: VmCommand) -> Vec<VmOperationResult> {
+    dedupe_names(&mut groups);
+
+    let mut results = Vec::new();
+    for (group_name, vm_names) in groups {
+        let mut rejected = client.command(&vm_names, group_name.as_str(), subscription_id, command).await;
+        for name in vm_names {
+            let failure = rejected.iter()
+                .position(|(rejected, _)| *rejected == name)
+                .map(|index| rejected.swap_remove(index).1);
+
+            if let Some(e) = &failure {
+                eprintln!("Failed to {} {name}: {e}", command.action().to_lowercase());
+            }
+            results.push(VmOperationResult {
+                name,
+                group: group_name.clone(),
+                succeeded: failure.is_none(),
+                skipped: false,
+                final_state: if failure.is_none() { "Accepted" } else { "Failed" }.to_owned(),
+                elapsed: Duration::ZERO
+            });
+        }
+    }
+    results
+}
+
+/// Sends `command` to the VMs of each group and waits for them to reach the
+/// target state, checking every `poll`, calling `progress` with the number of
+/// VMs done out of those sent the command. Returns the outcome for each VM.
+async fn send_vm_command(client: &impl VmOperations, mut groups: Vec<(String, Vec<String>)>, subscription_id: &str, command: VmCommand, timeout: Duration, poll: PollInterval, mut progress: impl FnMut(usize, usize)) -> Result<Vec<VmOperationResult>, error::AppError> {
+    let (action, target_state) = (command.action(), command.target_state());
+
+    // A VM named twice would be counted twice as it completes.
+    dedupe_names(&mut groups);
+
+    // Skip VMs that are already where the command would leave them, e.g.
+    // starting a running VM or powering off a deallocated one.
+    let mut results = Vec::new();
+    for &state in command.settled_states() {
+        for (group_name, vm_names) in groups.iter_mut() {
+            let already = client
+                .is_complete(vm_names, group_name.as_str(), subscription_id, state)
+                .await?;
+
+            vm_names.retain(|name| !already.contains(name));
+            results.extend(already.into_iter().map(|name| VmOperationResult {
+                name,
+                group: group_name.clone(),
+                succeeded: true,
+                skipped: true,
+                final_state: state.to_owned(),
+                elapsed: Duration::ZERO
+            }));
+        }
+    }
+
+    let started = Instant::now();
+    for (group_name, vm_names) in groups.iter_mut() {
+        let rejected = client.command(vm_names, group_name.as_str(), subscription_id, command).await;
+
+        vm_names.retain(|name| !rejected.iter().any(|(rejected, _)| rejected == name));
+        results.extend(rejected.into_iter().map(|(name, e)| {
+            eprintln!("Failed to {} {name}: {e}", action.to_lowercase());
+            VmOperationResult {
+                name,
+                group: group_name.clone(),
+                succeeded: false,
+                skipped: false,
+                final_state: "Failed".to_owned(),
+                elapsed: started.elapsed()
+            }
+        }));
+    }
+
+    let total: usize = groups.iter().map(|(_, vm_names)| vm_names.len()).sum();
+    let mut completed = 0;
+    progress(completed, total);
+
+    let describe = |groups: &[(String, Vec<String>)]| groups.iter()
+        .map(|(group_name, vm_names)| format!("{action} {} in {group_name}", vm_names.join(", ")))
+        .collect::<Vec<_>>()
+        .join("; ");
+    let pending = interrupt::pending(describe(&groups));
+
+    // When each VM was first seen without a power state, keyed by group and name.
+    let mut no_power_state: HashMap<(String, String), Instant> = HashMap::new();
+    let mut delay = poll.initial;
+
+    loop {
+        let mut throttled = false;
+        for (group_name, vm_names) in groups.iter_mut() {
+            // Leave the remaining groups for the next round rather than
+            // adding to the load while Azure is throttling.
+            if throttled {
+                break;
+            }
+
+            let states = client
+                .check_progress(vm_names, group_name.as_str(), subscription_id, target_state)
+                .await?;
+
+            let mut finished = Vec::new();
+            for (name, state) in states {
+                let result = |succeeded: bool, final_state: &str| VmOperationResult {
+                    name: name.clone(),
+                    group: group_name.clone(),
+                    succeeded,
+                    skipped: false,
+                    final_state: final_state.to_owned(),
+                    elapsed: started.elapsed()
+                };
+
+                match state {
+                    Progress::Complete => {
+                        completed += 1;
+                        results.push(result(true, target_state));
+                        finished.push(name);
+                    },
+                    Progress::Pending => {},
+                    Progress::Throttled => throttled = true,
+                    Progress::NoPowerState(codes) => {
+                        let since = *no_power_state.entry((group_name.clone(), name.clone()))
+                            .or_insert_with(Instant::now);
+
+                        if since.elapsed() >= NO_POWER_STATE_GRACE {
+                            let codes = codes.join(", ");
+                            eprintln!(
+                                "Giving up on {name}: no power state reported for {}s (statuses: {codes})",
+                                since.elapsed().as_secs()
+                            );
+                            results.push(result(false, &format!("No power state ({codes})")));
+                            finished.push(name);
+                        }
+                    }
+                }
+            }
+            vm_names.retain(|name| !finished.contains(name));
+        }
+        groups.retain(|(_, vm_names)| !vm_names.is_empty());
+        pending.update(describe(&groups));
+        progress(completed, total);
+
+        if groups.is_empty() || started.elapsed() >= timeout {
+            break;
+        }
+        if throttled {
+            delay = poll.throttled(delay);
+            warn!("Azure is throttling instance view requests, checking again in {}s", delay.as_secs());
+        }
+        sleep_until(Instant::now() + delay).await;
+
+        // Keep backing off while throttled rather than dropping back to `max`.
+        if !throttled {
+            delay = poll.next(delay);
+        }
+    }
+
+    for (group_name, vm_names) in groups {
+        results.extend(vm_names.into_iter().map(|name| VmOperationResult {
+            name,
+            group: group_name.clone(),
+            succeeded: false,
+            skipped: false,
+            final_state: "Timed out".to_owned(),
+            elapsed: started.elapsed()
+        }));
+    }
+    Ok(results)
+}
+
+async fn process_vm_cmd(args: VmArgs, store: &mut Store, creds: Arc<dyn TokenCredential>, max_in_flight: usize, concurrency: usize, timeout: Duration, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let client = VmClient::new(creds.clone())
+        .with_max_in_flight(max_in_flight)
+        .with_concurrency(concurrency);
+
+    fn get_opt<'a, F>(opt: &'a Option<String>, f: F) -> Result<&'a str, error::AppError>
+    where
+        F: FnOnce() -> Result<&'a str, error::AppError>
+    {
+        match opt.as_deref() {
+            Some(id) => Ok(id),
+            None => f()
+        }
+    }
+
+    match args.command {
+        VmCmd::Get { name, group, sub_id, id, raw, nsg, disks } => {
+            let id = match id {
+                Some(id) => Some(ResourceId::parse_vm(&id).ok_or(error::AppError::InvalidResourceId(id))?),
+                None => None
+            };
+
+            let (name, group_name, subscription_id) = match &id {
+                Some(id) => (id.name.as_str(), id.resource_group.as_str(), id.subscription_id.as_str()),
+                None => (
+                    name.as_deref().unwrap_or_default(),
+                    get_opt(&group, || store.get_resource_group().ok_or(error::AppError::NoRg))?,
+                    get_opt(&sub_id, || store.get_subscription_id().ok_or(error::AppError::NoSub))?
+                )
+            };
+
+            if nsg {
+                let vm = client.get_vm(name, group_name, subscription_id).await?;
+                let nics = client.get_nsg_rules(&vm).await?;
+                if !opts.quiet {
+                    for nic in &nics {
+                        match (&nic.nsg, nic.from_subnet) {
+                            (None, _) => eprintln!("NIC {} has no network security group on it or its subnet", nic.nic),
+                            (Some(_), true) => eprintln!("NIC {} has no network security group, showing the one on its subnet", nic.nic),
+                            (Some(_), false) => {}
+                        }
+                    }
+                }
+
+                display_nsg_rules(&nics, opts)?;
+                return Ok(());
+            }
+
+            if disks {
+                let vm = client.get_vm(name, group_name, subscription_id).await?;
+                display_disks(&client.get_disks(&vm).await?, opts)?;
+                return Ok(());
+            }
+
+            let (vm, addresses) = client.get_vm_with_instance_view(
+                name,
+                group_name,
+                subscription_id
+            ).await?;
+
+            if raw {
+                println!("{}", serde_json::to_string_pretty(&vm)?);
+                return Ok(());
+            }
+
+            display_vm_with_addresses(&vm, &addresses, opts)?;
+        },
+        VmCmd::List { group, sub_id, location, tag, running_only, stopped_only, watch, sort_by, reverse, limit } => {
+            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
+                .ok_or(error::AppError::NoSub))?;
+
+            let group_name = get_opt(&group, || store.get_resource_group()
+                .ok_or(error::AppError::NoRg))?;
+
+            let filter = VmFilter {
+                locations: location,
+                tags: tag,
+                limit,
+                state: StateFilter::from_flags(running_only, stopped_only)
+            };
+            loop {
+                let mut vms = client.list_vms_with_instance_view(
+                    group_name,
+                    subscription_id,
+                    &filter
+                ).await?;
+
+                if let Some(sort) = sort_by {
+                    sort.sort(&mut vms, reverse);
+                }
+
+                let Some(secs) = watch else {
+                    if !filter.is_empty() && vms.is_empty() {
+                        return Err(error::AppError::NoMatchingVms);
+                    }
+                    display_vm(Output::Multiple(&vms), opts)?;
+                    break;
+                };
+
+                // Clear the screen and move the cursor home before redrawing.
+                print!("\x1B[2J\x1B[H");
+                println!("Every {secs}s, press Ctrl-C to exit");
+                display_vm(Output::Multiple(&vms), opts)?;
+
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = sleep_until(Instant::now() + Duration::from_secs(secs)) => {}
+                }
+            }
+        },
+        VmCmd::ListAll { sub_id, location, tag, running_only, stopped_only, sort_by, reverse, limit } => {
+            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
+                .ok_or(error::AppError::NoSub))?;
+
+            let mut spinner = SpinnerGuard::new("Listing virtual machines...", opts);
+
+            // Pages can be slow to arrive in large subscriptions, so show a running count.
+            let filter = VmFilter {
+                locations: location,
+                tags: tag,
+                limit,
+                state: StateFilter::from_flags(running_only, stopped_only)
+            };
+            let mut vms = Vec::new();
+            let mut pages = pin!(client.list_all_vm_pages(subscription_id));
+            while let Some(page) = pages.try_next().await? {
+                vms.extend(page.into_iter().filter(|vm| filter.matches(vm) && filter.matches_state(vm)));
+                spinner.update(format!("Listing virtual machines... {} found", vms.len()));
+
+                if filter.is_satisfied(vms.len()) {
+                    break;
+                }
+            }
+            drop(spinner);
+            vms.truncate(limit.unwrap_or(usize::MAX));
+
+            if let Some(sort) = sort_by {
+                sort.sort(&mut vms, reverse);
+            }
+
+            if !filter.is_empty() && vms.is_empty() {
+                return Err(error::AppError::NoMatchingVms);
+            }
+            display_vm_all_groups(&vms, opts)?;
+        },
+        VmCmd::Start(args) => {
+            run_power_cmd(&client, &args, store, creds.clone(), VmCommand::Start, timeout, opts).await?;
+        },
+        VmCmd::Stop { args, no_deallocate } => {
+            let command = if no_deallocate { VmCommand::PowerOff } else { VmCommand::Stop };
+            run_power_cmd(&client, &args, store, creds.clone(), command, timeout, opts).await?;
+        },
+        VmCmd::Restart(args) => {
+            run_power_cmd(&client, &args, store, creds.clone(), VmCommand::Restart, timeout, opts).await?;
+        },
+        VmCmd::Resize { name, size, group, sub_id, wait } => {
+            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
+                .ok_or(error::AppError::NoSub))?;
+
+            let group_name = get_opt(&group, || store.get_resource_group()
+                .ok_or(error::AppError::NoRg))?;
+
+            if !opts.quiet {
+                eprintln!("Warning: resizing a running VM restarts it. Deallocate it first to avoid the reboot.");
+            }
+
+            let spinner = SpinnerGuard::new(format!("Resizing {name} to {size}..."), opts);
+            let vm = client.resize_vm(&name, &size, group_name, subscription_id, !wait.no_wait).await?;
+            drop(spinner);
+
+            match (vm, opts.quiet) {
+                (_, true) => {},
+                (Some(vm), false) => display_vm(Output::Single(&vm), opts)?,
+                (None, false) => println!("Resize of {name} to {size} accepted, not waiting for it to finish")
+            }
+        },
+        VmCmd::Tag { name, group, sub_id, add, remove } => {
+            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
+                .ok_or(error::AppError::NoSub))?;
+
+            let group_name = get_opt(&group, || store.get_resource_group()
+                .ok_or(error::AppError::NoRg))?;
+
+            let vm = client.update_tags(&name, &add, &remove, group_name, subscription_id).await?;
+            display_tags(vm.resource.tags.as_ref(), opts)?;
+        },
+        VmCmd::BootDiagnostics { name, group, sub_id, download } => {
+            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
+                .ok_or(error::AppError::NoSub))?;
+
+            let group_name = get_opt(&group, || store.get_resource_group()
+                .ok_or(error::AppError::NoRg))?;
+
+            let data = client.get_boot_diagnostics(&name, group_name, subscription_id).await?;
+            display_boot_diagnostics(&data, opts)?;
+
+            if let Some(dir) = download {
+                let files = [
+                    (data.console_screenshot_blob_uri.as_deref(), format!("{name}-screenshot.bmp")),
+                    (data.serial_console_log_blob_uri.as_deref(), format!("{name}-serial.log"))
+                ];
+
+                for (url, file) in files {
+                    if let Some(url) = url {
+                        download_file(url, &dir.join(file)).await?;
+                    }
+                }
+            }
+        },
+        VmCmd::InstanceView { name, group, sub_id } => {
+            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
+                .ok_or(error::AppError::NoSub))?;
+
+            let group_name = get_opt(&group, || store.get_resource_group()
+                .ok_or(error::AppError::NoRg))?;
+
+            let view = client.get_instance_view(&name, group_name, subscription_id).await?;
+            display_instance_view(&view, opts)?;
+        },
+        VmCmd::Examples => println!("{}", examples::render("vm")),
+        VmCmd::Images { alias } => {
+            let aliases: Vec<ImageAlias> = match alias {
+                Some(alias) => {
+                    let image = VmClient::resolve_image_alias(&alias)
+                        .ok_or_else(|| error::AppError::NotFound { kind: "image alias", name: alias.clone() })?;
+                    vec![ImageAlias { alias, image }]
+                },
+                None => IMAGE_ALIASES.iter()
+                    .filter_map(|(alias, _)| Some(ImageAlias {
+                        alias: alias.to_string(),
+                        image: VmClient::resolve_image_alias(alias)?
+                    }))
+                    .collect()
+            };
+
+            display_image_aliases(Output::Multiple(&aliases), opts)?;
+        },
+        VmCmd::Status { names, group, sub_id } => {
+            let subscription_id = get_opt(&sub_id, || store.get_subscription_id()
+                .ok_or(error::AppError::NoSub))?;
+
+            let group_name = get_opt(&group, || store.get_resource_group()
+                .ok_or(error::AppError::NoRg))?;
+
+            let names = match names {
+                Some(names) => expand_names(names)?,
+                None => client.list_vm_names(group_name, subscription_id).await?
+            };
+
+            let statuses = client.get_power_states(
+                names.iter(),
+                group_name,
+                subscription_id
+            ).await?;
+
+            display_vm_status(Output::Multiple(&statuses), opts)?;
+        }
+    }
+    Ok(())
+}
+
+async fn process_vault_cmd(args: VaultArgs, store: &Store, creds: Arc<dyn TokenCredential>, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let client = vault_client(creds);
+
+    match &args.command {
+        VaultCmd::List { group, sub_id } => {
+            let sub_id = match sub_id.as_deref() {
+                Some(id) => id,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
+            };
+
+            let pages = match group.as_deref() {
+                Some(group_name) => {
+                    info!("Listing vaults in resource group {group_name}");
+                    client.vaults_client().list_by_resource_group(sub_id, group_name).into_stream()
+                },
+                None => {
+                    info!("Listing vaults in {sub_id}");
+                    client.vaults_client().list_by_subscription_id(sub_id).into_stream()
+                }
+            };
+
+            let vaults: Vec<RecoveryVault> = timings::time("list vaults", pages.try_collect::<Vec<_>>())
+                .await?
+                .into_iter()
+                .flat_map(|vaults| vaults.value)
+                .collect();
+
+            display_vaults(Output::Multiple(&vaults), opts)?;
+        }
+    }
+    Ok(())
+}
+
+async fn process_recovery_cmd(args: RecoveryArgs, store: &Store, creds: Arc<dyn TokenCredential>, timeout: Duration, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let client = BackupClient::builder(creds.clone())
+        .retry(retry::options())
+        .transport(api_version::transport(Service::Backup))
+        .build();
+
+    match &args.command {
+        RecoveryCmd::List { vault_name, vault_group, sub_id, vm_name, group, since, until, fabric } => {
+            let sub_id = match sub_id.as_deref() {
+                Some(id) => id,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
+            };
+
+            let vault_name = match vault_name.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_name().ok_or(error::AppError::NoVault)?
+            };
+
+            let vault_group = match vault_group.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_resource_group().ok_or(error::AppError::NoVaultRg)?
+            };
+
+            if let Some(vm_name) = vm_name {
+                if let (Some(since), Some(until)) = (*since, *until) {
+                    if since > until {
+                        return Err(error::AppError::InvalidTimeRange { since, until });
+                    }
+                }
+
+                let group_name = match group.as_deref() {
+                    Some(name) => name,
+                    None => store.get_resource_group().ok_or(error::AppError::NoRg)?
+                };
+
+                let vault = Vault { name: vault_name, group: vault_group, sub_id, fabric };
+                let points = list_recovery_points(&client, &vault, group_name, vm_name, *since, *until).await?;
+                display_recovery_points(&points, opts)?;
+                return Ok(());
+            }
+
+            info!("Listing protected items in vault {vault_name}");
+            let items: Vec<ProtectedItemResource> = timings::time("list protected items", client.backup_protected_items_client()
+                .list(vault_name, vault_group, sub_id)
+                .filter("backupManagementType eq 'AzureIaasVM' and itemType eq 'VM'")
+                .into_stream()
+                .try_collect::<Vec<_>>())
+                .await?
+                .into_iter()
+                .flat_map(|items| items.value)
+                .collect();
+
+            display_protected_item(Output::Multiple(&items), opts)?;
+        },
+        RecoveryCmd::Restore { vault_name, vault_group, group, sub_id, vm_name, recovery_point, fabric } => {
+            let sub_id = match sub_id.as_deref() {
+                Some(id) => id,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
+            };
+
+            let vault_name = match vault_name.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_name().ok_or(error::AppError::NoVault)?
+            };
+
+            let vault_group = match vault_group.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_resource_group().ok_or(error::AppError::NoVaultRg)?
+            };
+
+            let group_name = match group.as_deref() {
+                Some(name) => name,
+                None => store.get_resource_group().ok_or(error::AppError::NoRg)?
+            };
+
+            let (container, item) = protected_item_names(group_name, vm_name);
+
+            let recovery_point = match recovery_point {
+                Some(point) => point.clone(),
+                None => {
+                    let vault = Vault { name: vault_name, group: vault_group, sub_id, fabric };
+                    let points = list_recovery_points(&client, &vault, group_name, vm_name, None, None).await?;
+
+                    if points.is_empty() {
+                        return Err(error::AppError::NoRecoveryPoints(vm_name.clone()));
+                    }
+
+                    display_recovery_points(&points, opts)?;
+
+                    let answer = prompt("Select a recovery point:")?
+                        .ok_or(error::AppError::NoRecoveryPoint)?;
+
+                    let index = answer.parse::<usize>().ok()
+                        .filter(|i| (1..=points.len()).contains(i))
+                        .ok_or(error::AppError::InvalidSelection(answer))?;
+
+                    points[index - 1].resource.name.clone().unwrap_or_default()
+                }
+            };
+
+            let vm = VmClient::new(creds).get_vm(vm_name, group_name, sub_id).await?;
+
+            // The request properties are a union keyed on `objectType`, so build
+            // them from the documented JSON shape.
+            let request: RestoreRequestResource = serde_json::from_value(json!({
+                "properties": {
+                    "objectType": "IaasVMRestoreRequest",
+                    "recoveryPointId": recovery_point,
+                    "recoveryType": "OriginalLocation",
+                    "sourceResourceId": vm.resource.id,
+                    "originalStorageAccountOption": false,
+                    "region": vm.resource.location
+                }
+            }))?;
+
+            info!("Triggering restore of {vm_name} from {recovery_point}");
+            let response = client.restores_client()
+                .trigger(vault_name, vault_group, sub_id, fabric, &container, &item, &recovery_point, request)
+                .send()
+                .await?
+                .into_raw_response();
+
+            let vault = Vault { name: vault_name, group: vault_group, sub_id, fabric };
+            wait_for_backup_operation(
+                &client,
+                &vault,
+                &response,
+                &format!("Restoring {vm_name} from {recovery_point}"),
+                timeout,
+                opts
+            ).await?;
+
+            if !opts.quiet {
+                println!("Restored {vm_name} from {recovery_point}");
+            }
+        },
+        RecoveryCmd::Disable { vault_name, vault_group, group, sub_id, names, delete_data, fabric, .. } => {
+            let sub_id = match sub_id.as_deref() {
+                Some(id) => id,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
+            };
+
+            let vault_name = match vault_name.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_name().ok_or(error::AppError::NoVault)?
+            };
+
+            let vault_group = match vault_group.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_resource_group().ok_or(error::AppError::NoVaultRg)?
+            };
+
+            let group_name = match group.as_deref() {
+                Some(name) => name,
+                None => store.get_resource_group().ok_or(error::AppError::NoRg)?
+            };
+
+            let vault = Vault { name: vault_name, group: vault_group, sub_id, fabric };
+            let done = if *delete_data { "Backup data deleted" } else { "Protection stopped" };
+
+            let mut results = Vec::new();
+            for vm_name in expand_names(names.clone())? {
+                let result = disable_protection(&client, &vault, group_name, &vm_name, *delete_data, timeout, opts).await;
+                results.push((vm_name, result));
+            }
+            report_vm_results(results, done, opts)?;
+        },
+        RecoveryCmd::TriggerBackup { vault_name, vault_group, group, sub_id, names, retain_days, fabric } => {
+            let sub_id = match sub_id.as_deref() {
+                Some(id) => id,
+                None => store.get_subscription_id().ok_or(error::AppError::NoSub)?
+            };
+
+            let vault_name = match vault_name.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_name().ok_or(error::AppError::NoVault)?
+            };
+
+            let vault_group = match vault_group.as_deref() {
+                Some(name) => name,
+                None => store.get_vault_resource_group().ok_or(error::AppError::NoVaultRg)?
+            };
+
+            let group_name = match group.as_deref() {
+                Some(name) => name,
+                None => store.get_resource_group().ok_or(error::AppError::NoRg)?
+            };
+
+            let vault = Vault { name: vault_name, group: vault_group, sub_id, fabric };
+            let expiry = OffsetDateTime::now_utc() + time::Duration::days(i64::from(*retain_days));
+
+            let mut results = Vec::new();
+            for vm_name in expand_names(names.clone())? {
+                let result = trigger_backup(&client, &vault, group_name, &vm_name, expiry, timeout, opts).await;
+                results.push((vm_name, result));
+            }
+            report_vm_results(results, "Backed up", opts)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows how each VM fared in a per-VM backup operation, failing with
+/// `AppError::Incomplete` unless every one reached `done`.
+fn report_vm_results(results: Vec<(String, Result<(), error::AppError>)>, done: &str, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let mut statuses = Vec::with_capacity(results.len());
+    let mut failed = Vec::new();
+    let mut timed_out = Vec::new();
+    for (vm_name, result) in results {
+        let status = match result {
+            Ok(()) => done.to_owned(),
+            Err(error::AppError::Timeout { .. }) => {
+                timed_out.push(vm_name.clone());
+                "Timed out".to_owned()
+            },
+            Err(e) => {
+                eprintln!("{vm_name}: {e}");
+                failed.push(vm_name.clone());
+                "Failed".to_owned()
+            }
+        };
+        statuses.push(VmStatus { name: vm_name, status });
+    }
+
+    display_vm_status(Output::Multiple(&statuses), opts)?;
+    if !failed.is_empty() || !timed_out.is_empty() {
+        return Err(error::AppError::Incomplete { failed, timed_out });
+    }
     Ok(())
 }
 
-async fn process_cmds(cli: Cli, store: &mut Store, creds: Arc<dyn TokenCredential>) -> Result<(), Box<dyn std::error::Error>> {
+/// Takes an on-demand backup of a protected VM, keeping the recovery point
+/// until `expiry`, and waits for it to finish.
+async fn trigger_backup(client: &BackupClient, vault: &Vault<'_>, group_name: &str, vm_name: &str, expiry: OffsetDateTime, timeout: Duration, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let (container, item) = protected_item_names(group_name, vm_name);
+    let expiry = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        expiry.year(), u8::from(expiry.month()), expiry.day(), expiry.hour(), expiry.minute(), expiry.second()
+    );
+
+    // The request properties are a union keyed on `objectType`, so build
+    // them from the documented JSON shape.
+    let request: BackupRequestResource = serde_json::from_value(json!({
+        "properties": {
+            "objectType": "IaasVMBackupRequest",
+            "recoveryPointExpiryTimeInUTC": expiry
+        }
+    }))?;
+
+    info!("Triggering backup of {vm_name}");
+    let response = client.backups_client()
+        .trigger(vault.name, vault.group, vault.sub_id, vault.fabric, &container, &item, request)
+        .send()
+        .await
+        .map_err(error::AppError::or_not_found("protected item", vm_name))?
+        .into_raw_response();
+
+    wait_for_backup_operation(client, vault, &response, &format!("Backing up {vm_name}"), timeout, opts).await
+}
+
+/// A Recovery Services vault, where it lives, and the backup fabric used
+/// within it.
+struct Vault<'a> {
+    name: &'a str,
+    group: &'a str,
+    sub_id: &'a str,
+    fabric: &'a str
+}
+
+/// The protection container and protected item names Azure Backup uses for
+/// an IaaS VM.
+fn protected_item_names(group_name: &str, vm_name: &str) -> (String, String) {
+    (
+        format!("iaasvmcontainer;iaasvmcontainerv2;{group_name};{vm_name}"),
+        format!("vm;iaasvmcontainerv2;{group_name};{vm_name}")
+    )
+}
+
+/// Lists the recovery points of a VM taken between `since` and `until`,
+/// newest first.
+async fn list_recovery_points(client: &BackupClient, vault: &Vault<'_>, group_name: &str, vm_name: &str, since: Option<DateTime<FixedOffset>>, until: Option<DateTime<FixedOffset>>) -> Result<Vec<RecoveryPointResource>, error::AppError> {
+    let (container, item) = protected_item_names(group_name, vm_name);
+
+    info!("Listing recovery points of {vm_name}");
+    let points: Vec<RecoveryPointResource> = timings::time("list recovery points", client.recovery_points_client()
+        .list(vault.name, vault.group, vault.sub_id, vault.fabric, &container, &item)
+        .into_stream()
+        .try_collect::<Vec<_>>())
+        .await?
+        .into_iter()
+        .flat_map(|points| points.value)
+        .collect();
+
+    // The properties are a union per workload type, but they all carry the
+    // time the point was taken.
+    let points = points.into_iter()
+        .map(|point| {
+            let time = point.properties.as_ref()
+                .and_then(|properties| serde_json::to_value(properties).ok())
+                .and_then(|properties| properties["recoveryPointTime"].as_str().and_then(|time| DateTime::parse_from_rfc3339(time).ok()));
+            (time, point)
+        })
+        .collect();
+
+    Ok(newest_first(points, since, until))
+}
+
+/// Keeps the points taken between `since` and `until`, newest first. Points
+/// without a time are kept and listed last.
+fn newest_first<T>(points: Vec<(Option<DateTime<FixedOffset>>, T)>, since: Option<DateTime<FixedOffset>>, until: Option<DateTime<FixedOffset>>) -> Vec<T> {
+    let in_range = |time: &DateTime<FixedOffset>| since.is_none_or(|since| *time >= since) && until.is_none_or(|until| *time <= until);
+
+    let mut points: Vec<_> = points.into_iter()
+        .filter(|(time, _)| match time {
+            Some(time) => in_range(time),
+            None => true
+        })
+        .collect();
+
+    points.sort_by(|(a, _), (b, _)| b.cmp(a));
+    points.into_iter().map(|(_, point)| point).collect()
+}
+
+/// Stops backing up a VM, deleting its recovery points too when `delete_data`
+/// is set, and waits for Azure to finish.
+async fn disable_protection(client: &BackupClient, vault: &Vault<'_>, group_name: &str, vm_name: &str, delete_data: bool, timeout: Duration, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    let (container, item) = protected_item_names(group_name, vm_name);
+    let items = client.protected_items_client();
+
+    let response = if delete_data {
+        info!("Deleting backup data of {vm_name}");
+        items.delete(vault.name, vault.group, vault.sub_id, vault.fabric, &container, &item)
+            .send()
+            .await
+            .map_err(error::AppError::or_not_found("protected item", vm_name))?
+            .into_raw_response()
+    } else {
+        let existing = items.get(vault.name, vault.group, vault.sub_id, vault.fabric, &container, &item)
+            .await
+            .map_err(error::AppError::or_not_found("protected item", vm_name))?;
+        let existing = serde_json::to_value(existing)?;
+
+        // Like the restore request, the properties are a union keyed on
+        // `protectedItemType`, so build them from the documented JSON shape.
+        let request: ProtectedItemResource = serde_json::from_value(json!({
+            "properties": {
+                "protectedItemType": existing["properties"]["protectedItemType"],
+                "sourceResourceId": existing["properties"]["sourceResourceId"],
+                "protectionState": "ProtectionStopped"
+            }
+        }))?;
+
+        info!("Stopping protection of {vm_name}");
+        items.create_or_update(vault.name, vault.group, vault.sub_id, vault.fabric, &container, &item, request)
+            .send()
+            .await?
+            .into_raw_response()
+    };
+
+    let description = if delete_data {
+        format!("Deleting backup data of {vm_name}")
+    } else {
+        format!("Stopping protection of {vm_name}")
+    };
+    wait_for_backup_operation(client, vault, &response, &description, timeout, opts).await
+}
+
+/// Waits for the backup operation started by `response`, showing
+/// `description` with the elapsed time while it runs. Responses other than
+/// `202 Accepted` mean the operation already finished.
+async fn wait_for_backup_operation(client: &BackupClient, vault: &Vault<'_>, response: &Response, description: &str, timeout: Duration, opts: &DisplayOptions) -> Result<(), error::AppError> {
+    if response.status() != StatusCode::Accepted {
+        return Ok(());
+    }
+
+    let mut spinner = SpinnerGuard::new(format!("{description}..."), opts);
+    let _pending = interrupt::pending(description);
+
+    let started = Instant::now();
+    operation::poll_operation(response, timeout, |operation_id| {
+        spinner.update(format!("{description}... ({}s)", started.elapsed().as_secs()));
+
+        let statuses = client.backup_operation_statuses_client();
+        async move {
+            let operation = timings::time("backup operation status", statuses.get(vault.name, vault.group, vault.sub_id, operation_id)).await?;
+            match operation.status {
+                Some(OperationState::InProgress) => Ok(false),
+                Some(OperationState::Succeeded) => Ok(true),
+                status => Err(error::AppError::OperationFailed(
+                    format!("{description} finished with status {status:?}")
+                ))
+            }
+        }
+    }).await
+}
+
+async fn process_cmds(cli: Cli, store: &mut Store, creds: Arc<dyn TokenCredential>) -> Result<(), error::AppError> {
+    let output_file = cli.output_file.as_deref()
+        .map(|path| output_file(path, cli.overwrite, cli.append))
+        .transpose()?;
+
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let opts = DisplayOptions {
+        format: cli.output,
+        color: !cli.no_color && !no_color && cli.output_file.is_none(),
+        style: cli.style,
+        columns: cli.columns,
+        wide: cli.wide,
+        quiet: cli.quiet,
+        compact: cli.compact,
+        output_file
+    };
+
     match cli.command {
         Some(Cmd::Sub(args)) => {
-            process_sub_cmd(args, &store, creds).await?;
+            process_sub_cmd(args, &store, creds, &opts).await?;
         },
         Some(Cmd::Rg(args)) => {
-            process_rg_cmd(args, &store, creds).await?;
+            process_rg_cmd(args, &store, creds, cli.concurrency, &opts).await?;
         },
         Some(Cmd::Vm(args)) => {
-            process_vm_cmd(args, &store, creds).await?;
+            process_vm_cmd(args, store, creds, cli.max_in_flight, cli.concurrency, Duration::from_secs(cli.timeout), &opts).await?;
+        },
+        Some(Cmd::Recovery(args)) => {
+            process_recovery_cmd(args, &store, creds, Duration::from_secs(cli.timeout), &opts).await?;
+        },
+        Some(Cmd::Vault(args)) => {
+            process_vault_cmd(args, &store, creds, &opts).await?;
         },
+        Some(Cmd::Profile(args)) => {
+            process_profile_cmd(args, store).await?;
+        },
+        Some(Cmd::Config(args)) => {
+            process_config_cmd(args, store, &opts).await?;
+        },
+        Some(Cmd::Completions { .. }) => unreachable!("completions are printed before loading the store"),
+        Some(Cmd::Doctor) => unreachable!("doctor runs before loading the store"),
         None => {
             println!("No command specified");
         }
@@ -400,21 +2592,288 @@ fn config() {
 #[cfg(not(windows))]
 fn config() {}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn run(cli: Cli) -> Result<(), error::AppError> {
+    let (show_timings, style) = (cli.timings, cli.style);
+    if show_timings {
+        timings::enable();
+    }
+    let color = !cli.no_color && !env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
 
-    config();
+    let result = run_cmds(cli).await;
+    if show_timings {
+        display_timings(&timings::report(), style, color);
+    }
+    result
+}
 
-    let cli = Cli::parse();
+async fn run_cmds(cli: Cli) -> Result<(), error::AppError> {
+    if let Some(Cmd::Completions { shell }) = cli.command {
+        let mut command = Cli::command();
+        let name = command.get_name().to_owned();
+        clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+        return Ok(());
+    }
+
+    // The doctor reports a broken store file rather than failing on it.
+    if let Some(Cmd::Doctor) = cli.command {
+        let color = !cli.no_color && !env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        return doctor::run(&cli, color).await;
+    }
 
     let mut store = Store::get_or_create().await?;
-    handle_globals(&cli, &mut store).await?;
+    if let Some(profile) = cli.profile.as_deref() {
+        debug!("Using profile: {profile}");
+        store.override_profile(profile)?;
+    }
+
+    retry::configure(cli.max_retries, cli.retry_delay.map(Duration::from_millis));
+    api_version::configure(api_versions(&cli, &store));
+
+    debug!("Creating Azure credentials using {:?}", cli.auth);
+    let creds = credential(cli.auth)?;
+    if let Some(sub) = cli.subscription.as_deref() {
+        let sub_id = resolve_subscription_id(&subscription_client(creds.clone()), sub).await?;
+        debug!("Using subscription {sub_id} for this command");
+        store.override_subscription_id(&sub_id);
+    }
+    if let Some(group) = cli.resource_group.as_deref() {
+        debug!("Using resource group {group} for this command");
+        store.override_resource_group(group);
+    }
+    handle_globals(&cli, &mut store, creds.clone()).await?;
 
     if cli.command.is_some() {
-        debug!("Creating Azure credentials");
-        let creds = Arc::new(AzureCliCredential::new());
         process_cmds(cli, &mut store, creds).await?;
     }
 
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> ExitCode {
+
+    config();
+
+    let cli = Cli::parse();
+
+    let level = match cli.verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+
+    // Polled first so that `vm list --watch`, which ends cleanly on Ctrl-C
+    // itself, finishes before the interrupt is reported.
+    let mut task = Box::pin(run(cli));
+    let finished = tokio::select! {
+        biased;
+        result = &mut task => Ok(result),
+        _ = tokio::signal::ctrl_c() => Err(interrupt::snapshot())
+    };
+
+    let result = match finished {
+        Ok(result) => result,
+        Err(pending) => {
+            // Dropping the task stops any spinner before the report is printed.
+            drop(task);
+            interrupt::report(&pending);
+            return ExitCode::from(interrupt::EXIT_CODE);
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Reports every VM as `waiting` until it has been polled `polls_needed`
+    /// times, recording when each poll happened.
+    struct MockVmClient {
+        polls_needed: usize,
+        waiting: Progress,
+        polls: Mutex<Vec<Instant>>,
+        commanded: Mutex<Vec<String>>
+    }
+
+    impl MockVmClient {
+        fn new(polls_needed: usize) -> Self {
+            Self::reporting(polls_needed, Progress::Pending)
+        }
+
+        fn reporting(polls_needed: usize, waiting: Progress) -> Self {
+            Self { polls_needed, waiting, polls: Mutex::new(Vec::new()), commanded: Mutex::new(Vec::new()) }
+        }
+
+        /// The whole seconds between each poll and the next.
+        fn poll_gaps(&self) -> Vec<u64> {
+            self.polls.lock().unwrap().windows(2).map(|polls| (polls[1] - polls[0]).as_secs()).collect()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl VmOperations for MockVmClient {
+        async fn command(&self, vm_names: &[String], _: &str, _: &str, _: VmCommand) -> Vec<(String, error::AppError)> {
+            self.commanded.lock().unwrap().extend(vm_names.iter().cloned());
+            Vec::new()
+        }
+
+        async fn is_complete(&self, _: &[String], _: &str, _: &str, _: &str) -> Result<Vec<String>, error::AppError> {
+            Ok(Vec::new())
+        }
+
+        async fn check_progress(&self, vm_names: &[String], _: &str, _: &str, _: &str) -> Result<Vec<(String, Progress)>, error::AppError> {
+            let mut polls = self.polls.lock().unwrap();
+            polls.push(Instant::now());
+
+            let progress = if polls.len() >= self.polls_needed { Progress::Complete } else { self.waiting.clone() };
+            Ok(vm_names.iter().map(|name| (name.clone(), progress.clone())).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn vm_reaches_state_on_second_poll() {
+        let client = MockVmClient::new(2);
+        let groups = vec![("rg-web".to_owned(), vec!["web01".to_owned()])];
+
+        let mut updates = Vec::new();
+        let poll = PollInterval { initial: Duration::from_millis(10), max: Duration::from_millis(10) };
+        let results = send_vm_command(&client, groups, "sub", VmCommand::Start, Duration::from_secs(30), poll, |completed, total| {
+            updates.push((completed, total));
+        }).await.unwrap();
+
+        assert_eq!(*client.commanded.lock().unwrap(), ["web01"]);
+        assert_eq!(client.polls.lock().unwrap().len(), 2);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].succeeded && !results[0].skipped);
+        assert_eq!(results[0].final_state, "VM running");
+        assert_eq!(updates.first(), Some(&(0, 1)));
+        assert_eq!(updates.last(), Some(&(1, 1)));
+    }
+
+    #[tokio::test]
+    async fn duplicate_names_are_sent_once() {
+        let client = MockVmClient::new(1);
+        let names = ["web01", "WEB01", "web02"].map(str::to_owned).to_vec();
+
+        let mut updates = Vec::new();
+        let poll = PollInterval { initial: Duration::from_millis(10), max: Duration::from_millis(10) };
+        let results = send_vm_command(&client, vec![("rg-web".to_owned(), names)], "sub", VmCommand::Start, Duration::from_secs(30), poll, |completed, total| {
+            updates.push((completed, total));
+        }).await.unwrap();
+
+        assert_eq!(*client.commanded.lock().unwrap(), ["web01", "web02"]);
+        assert_eq!(results.len(), 2);
+        assert_eq!(updates.last(), Some(&(2, 2)));
+    }
+
+    #[tokio::test]
+    async fn no_wait_sends_without_polling() {
+        let client = MockVmClient::new(1);
+        let names = ["web01", "web02", "WEB02"].map(str::to_owned).to_vec();
+
+        let results = dispatch_vm_command(&client, vec![("rg-web".to_owned(), names)], "sub", VmCommand::Stop).await;
+
+        assert_eq!(*client.commanded.lock().unwrap(), ["web01", "web02"]);
+        assert_eq!(client.polls.lock().unwrap().len(), 0);
+        assert!(results.iter().all(|result| result.succeeded && result.final_state == "Accepted"));
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn poll_interval_backs_off_gently() {
+        let poll = PollInterval { initial: Duration::from_secs(2), max: Duration::from_secs(10) };
+        let mut delays = vec![poll.initial];
+        for _ in 0..3 {
+            delays.push(poll.next(*delays.last().unwrap()));
+        }
+        assert_eq!(delays, [2, 5, 10, 10].map(Duration::from_secs));
+    }
+
+    #[test]
+    fn throttled_polls_double_up_to_a_minute_or_max() {
+        let poll = PollInterval { initial: Duration::from_secs(2), max: Duration::from_secs(10) };
+        assert_eq!(poll.throttled(Duration::from_secs(2)), Duration::from_secs(10));
+        assert_eq!(poll.throttled(Duration::from_secs(10)), Duration::from_secs(20));
+        assert_eq!(poll.throttled(Duration::from_secs(40)), MAX_THROTTLED_POLL_INTERVAL);
+
+        let poll = PollInterval { initial: Duration::from_secs(2), max: Duration::from_secs(120) };
+        assert_eq!(poll.throttled(Duration::from_secs(2)), Duration::from_secs(120));
+        assert_eq!(poll.throttled(Duration::from_secs(120)), Duration::from_secs(120));
+    }
+
+    #[tokio::test]
+    async fn throttled_polls_keep_backing_off() {
+        tokio::time::pause();
+        let client = MockVmClient::reporting(5, Progress::Throttled);
+        let groups = vec![("rg-web".to_owned(), vec!["web01".to_owned()])];
+
+        let poll = PollInterval { initial: Duration::from_secs(2), max: Duration::from_secs(10) };
+        let results = send_vm_command(&client, groups, "sub", VmCommand::Start, Duration::from_secs(600), poll, |_, _| {}).await.unwrap();
+
+        assert!(results[0].succeeded);
+        assert_eq!(client.poll_gaps(), [10, 20, 40, 60]);
+    }
+
+    #[tokio::test]
+    async fn throttled_polls_wait_at_least_max() {
+        tokio::time::pause();
+        let client = MockVmClient::reporting(3, Progress::Throttled);
+        let groups = vec![("rg-web".to_owned(), vec!["web01".to_owned()])];
+
+        let poll = PollInterval { initial: Duration::from_secs(2), max: Duration::from_secs(120) };
+        let results = send_vm_command(&client, groups, "sub", VmCommand::Start, Duration::from_secs(600), poll, |_, _| {}).await.unwrap();
+
+        assert!(results[0].succeeded);
+        assert_eq!(client.poll_gaps(), [120, 120]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_vms_without_a_power_state() {
+        tokio::time::pause();
+        let codes = vec!["ProvisioningState/creating".to_owned(), "OSState/generalized".to_owned()];
+        let client = MockVmClient::reporting(usize::MAX, Progress::NoPowerState(codes));
+        let groups = vec![("rg-web".to_owned(), vec!["web01".to_owned()])];
+
+        let poll = PollInterval { initial: Duration::from_secs(10), max: Duration::from_secs(10) };
+        let results = send_vm_command(&client, groups, "sub", VmCommand::Start, Duration::from_secs(600), poll, |_, _| {}).await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].succeeded);
+        assert_eq!(results[0].final_state, "No power state (ProvisioningState/creating, OSState/generalized)");
+        assert!(results[0].elapsed >= NO_POWER_STATE_GRACE && results[0].elapsed < Duration::from_secs(600));
+    }
+
+    #[test]
+    fn recovery_points_are_filtered_and_newest_first() {
+        let time = |value| Some(parse_timestamp(value).unwrap());
+        let points = vec![
+            (time("2024-05-01T00:00:00Z"), "may"),
+            (None, "unknown"),
+            (time("2024-07-01T00:00:00+02:00"), "july"),
+            (time("2024-04-30T23:59:59Z"), "april"),
+            (time("2024-06-01T00:00:00Z"), "june"),
+            (time("2024-08-01T00:00:00Z"), "august")
+        ];
+
+        assert_eq!(newest_first(points.clone(), None, None), ["august", "july", "june", "may", "april", "unknown"]);
+
+        let since = time("2024-05-01T00:00:00Z");
+        let until = time("2024-06-30T22:00:00Z");
+        assert_eq!(newest_first(points, since, until), ["july", "june", "may", "unknown"]);
+        assert!(parse_timestamp("2024-05-01").is_err());
+    }
+}
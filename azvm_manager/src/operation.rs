@@ -0,0 +1,52 @@
+use azure_core::headers::HeaderName;
+use azure_core::Response;
+use std::future::Future;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::error::AppError;
+
+/// Poll interval used when Azure doesn't send a `retry-after` header.
+const DEFAULT_RETRY_AFTER: u64 = 5;
+
+/// Waits for the long-running operation started by `response` to finish.
+///
+/// The operation id is read from the `azure-asyncoperation` (or `location`)
+/// header and handed to `check`, which returns `Ok(true)` once the operation
+/// has succeeded, `Ok(false)` while it is still running, and an error if it failed.
+/// Gives up with `AppError::Timeout` once `timeout` has elapsed.
+pub async fn poll_operation<F, Fut>(response: &Response, timeout: Duration, mut check: F) -> Result<(), AppError>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<bool, AppError>>
+{
+    let headers = response.headers();
+    let operation_url = headers.get_optional_str(&HeaderName::from_static("azure-asyncoperation"))
+        .or_else(|| headers.get_optional_str(&HeaderName::from_static("location")))
+        .ok_or(AppError::MissingOperation)?;
+
+    let operation_id = operation_id(operation_url)
+        .ok_or_else(|| AppError::InvalidOperation(operation_url.to_owned()))?;
+
+    let retry_after = headers.get_optional_str(&HeaderName::from_static("retry-after"))
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_RETRY_AFTER);
+
+    let deadline = Instant::now() + timeout;
+    while !check(operation_id.clone()).await? {
+        if Instant::now() >= deadline {
+            return Err(AppError::Timeout { seconds: timeout.as_secs(), pending: vec![operation_id] });
+        }
+        sleep_until(Instant::now() + Duration::from_secs(retry_after)).await;
+    }
+    Ok(())
+}
+
+/// Extracts the trailing operation id from an operation URL such as
+/// `.../backupOperations/{id}?api-version=...`.
+fn operation_id(url: &str) -> Option<String> {
+    url.split('?')
+        .next()
+        .and_then(|path| path.trim_end_matches('/').rsplit('/').next())
+        .filter(|id| !id.is_empty())
+        .map(|id| id.to_owned())
+}
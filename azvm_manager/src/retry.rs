@@ -0,0 +1,31 @@
+use azure_core::{ExponentialRetryOptions, RetryOptions};
+use std::sync::OnceLock;
+use tokio::time::Duration;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Settings {
+    max_retries: Option<u32>,
+    initial_delay: Option<Duration>
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Overrides the retry policy of every client built afterwards. Settings left
+/// as `None` keep the Azure SDK defaults.
+pub fn configure(max_retries: Option<u32>, initial_delay: Option<Duration>) {
+    let _ = SETTINGS.set(Settings { max_retries, initial_delay });
+}
+
+/// The retry policy to build Azure clients with.
+pub fn options() -> RetryOptions {
+    let settings = SETTINGS.get().copied().unwrap_or_default();
+
+    let mut options = ExponentialRetryOptions::default();
+    if let Some(max_retries) = settings.max_retries {
+        options = options.max_retries(max_retries);
+    }
+    if let Some(initial_delay) = settings.initial_delay {
+        options = options.initial_delay(initial_delay);
+    }
+    RetryOptions::exponential(options)
+}
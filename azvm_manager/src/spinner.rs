@@ -0,0 +1,27 @@
+use dsp::DisplayOptions;
+use spinoff::{Spinner, spinners, Color};
+use std::borrow::Cow;
+
+/// A progress spinner that stops itself when dropped, so an early return or
+/// `?` never leaves it animating. Does nothing when progress is hidden.
+pub struct SpinnerGuard(Option<Spinner>);
+
+impl SpinnerGuard {
+    pub fn new(message: impl Into<Cow<'static, str>>, opts: &DisplayOptions) -> Self {
+        Self(opts.show_progress().then(|| Spinner::new(spinners::Dots, message, Color::Blue)))
+    }
+
+    pub fn update(&mut self, message: impl Into<Cow<'static, str>>) {
+        if let Some(spinner) = self.0.as_mut() {
+            spinner.update(spinners::Dots, message, Color::Blue);
+        }
+    }
+}
+
+impl Drop for SpinnerGuard {
+    fn drop(&mut self) {
+        if let Some(spinner) = self.0.take() {
+            spinner.stop();
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use dsp::Timing;
+use std::collections::BTreeMap;
+use std::future::IntoFuture;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TIMINGS: Mutex<BTreeMap<&'static str, (u32, Duration)>> = Mutex::new(BTreeMap::new());
+
+/// Starts recording how long each timed operation takes.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Awaits `operation`, recording its duration under `name` when timings are enabled.
+pub async fn time<F: IntoFuture>(name: &'static str, operation: F) -> F::Output {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return operation.await;
+    }
+
+    let started = Instant::now();
+    let output = operation.await;
+    let elapsed = started.elapsed();
+
+    if let Ok(mut timings) = TIMINGS.lock() {
+        let (count, total) = timings.entry(name).or_default();
+        *count += 1;
+        *total += elapsed;
+    }
+    output
+}
+
+/// The recorded timings, ordered by operation name.
+pub fn report() -> Vec<Timing> {
+    let timings = match TIMINGS.lock() {
+        Ok(timings) => timings,
+        Err(poisoned) => poisoned.into_inner()
+    };
+
+    timings.iter()
+        .map(|(operation, (count, total))| Timing {
+            operation: operation.to_string(),
+            count: *count,
+            total: *total
+        })
+        .collect()
+}
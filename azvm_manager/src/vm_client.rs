@@ -1,69 +1,459 @@
 use std::sync::Arc;
+use async_trait::async_trait;
 use azure_core::auth::TokenCredential;
-use azure_core::{ExponentialRetryOptions, RetryOptions};
 use azure_mgmt_compute::{Client, models::VirtualMachine};
-use azure_mgmt_compute::models::{VirtualMachineInstanceView, VirtualMachineProperties};
-use futures_util::TryStreamExt;
+use azure_mgmt_compute::models::{
+    Disk, HardwareProfile, ImageReference, RetrieveBootDiagnosticsDataResult, VirtualMachineInstanceView,
+    VirtualMachineProperties, VirtualMachineUpdate
+};
+use azure_mgmt_network::Client as NetworkClient;
+use azure_mgmt_network::models::{NetworkInterface, SecurityRule, SecurityRuleAccess, SecurityRuleDirection};
+use dsp::{power_state, DiskEncryption, NetworkAddresses, NicSecurity, NsgRule, VmDisk, VmStatus};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use log::{info, warn};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::pin::pin;
+
+use crate::api_version::{self, Service};
+use crate::error::AppError;
+use crate::retry;
+use crate::timings;
+
+/// Default upper bound on the number of Azure requests issued at once.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Default number of start/stop requests dispatched at once.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 10;
 
 #[derive(Debug, Copy, Clone)]
 pub enum VmCommand {
     Start,
-    Stop
+    /// Stops and deallocates, releasing the compute.
+    Stop,
+    /// Stops but keeps the VM allocated, along with its dynamic IPs.
+    PowerOff,
+    Restart
+}
+
+impl VmCommand {
+    pub fn action(self) -> &'static str {
+        match self {
+            VmCommand::Start => "Start",
+            VmCommand::Stop => "Stop",
+            VmCommand::PowerOff => "Power off",
+            VmCommand::Restart => "Restart"
+        }
+    }
+
+    pub fn past_tense(self) -> &'static str {
+        match self {
+            VmCommand::Start => "Started",
+            VmCommand::Stop => "Stopped",
+            VmCommand::PowerOff => "Powered off",
+            VmCommand::Restart => "Restarted"
+        }
+    }
+
+    /// The power state VMs are in once the command has finished.
+    pub fn target_state(self) -> &'static str {
+        match self {
+            VmCommand::Start | VmCommand::Restart => "VM running",
+            VmCommand::Stop => "VM deallocated",
+            VmCommand::PowerOff => "VM stopped"
+        }
+    }
+
+    /// The power states in which sending the command would be a wasted call,
+    /// as the VM is already where the command would leave it.
+    pub fn settled_states(self) -> &'static [&'static str] {
+        match self {
+            VmCommand::Start => &["VM running"],
+            VmCommand::Stop => &["VM deallocated"],
+            VmCommand::PowerOff => &["VM stopped", "VM deallocated"],
+            // Restarting a running VM is the point.
+            VmCommand::Restart => &[]
+        }
+    }
+}
+
+/// Short names for common marketplace images, as accepted by
+/// `az vm create --image`, with their `publisher:offer:sku:version`.
+pub const IMAGE_ALIASES: [(&str, &str); 12] = [
+    ("CentOS85Gen2", "OpenLogic:CentOS:8_5-gen2:latest"),
+    ("Debian11", "Debian:debian-11:11-backports-gen2:latest"),
+    ("Debian12", "Debian:debian-12:12-gen2:latest"),
+    ("RHELRaw8LVMGen2", "RedHat:RHEL:8-lvm-gen2:latest"),
+    ("SuseSles15SP5", "SUSE:sles-15-sp5:gen2:latest"),
+    ("Ubuntu2204", "Canonical:0001-com-ubuntu-server-jammy:22_04-lts-gen2:latest"),
+    ("Ubuntu2404", "Canonical:ubuntu-24_04-lts:server:latest"),
+    ("UbuntuLTS", "Canonical:UbuntuServer:18.04-LTS:latest"),
+    ("Win2016Datacenter", "MicrosoftWindowsServer:WindowsServer:2016-Datacenter:latest"),
+    ("Win2019Datacenter", "MicrosoftWindowsServer:WindowsServer:2019-Datacenter:latest"),
+    ("Win2022AzureEditionCore", "MicrosoftWindowsServer:WindowsServer:2022-datacenter-azure-edition-core:latest"),
+    ("Win2022Datacenter", "MicrosoftWindowsServer:WindowsServer:2022-datacenter-g2:latest")
+];
+
+/// The components of an ARM resource ID such as
+/// `/subscriptions/{sub}/resourceGroups/{group}/providers/{namespace}/{type}/{name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceId {
+    pub subscription_id: String,
+    pub resource_group: String,
+    pub name: String
+}
+
+impl ResourceId {
+    pub fn parse(id: &str) -> Option<Self> {
+        let segments: Vec<&str> = id.trim_matches('/').split('/').collect();
+
+        Some(Self {
+            subscription_id: Self::segment_after(id, "subscriptions")?.to_owned(),
+            resource_group: Self::segment_after(id, "resourceGroups")?.to_owned(),
+            name: segments.last().filter(|s| !s.is_empty())?.to_string()
+        })
+    }
+
+    /// The segment following `key` in a resource ID, e.g. the virtual network
+    /// of a subnet ID for `virtualNetworks`.
+    pub fn segment_after<'a>(id: &'a str, key: &str) -> Option<&'a str> {
+        let mut segments = id.trim_matches('/').split('/');
+        segments.position(|s| s.eq_ignore_ascii_case(key))?;
+        segments.next()
+    }
+
+    /// Parses the ID of a virtual machine, requiring exactly the shape
+    /// `/subscriptions/{sub}/resourceGroups/{group}/providers/Microsoft.Compute/virtualMachines/{name}`.
+    pub fn parse_vm(id: &str) -> Option<Self> {
+        let segments: Vec<&str> = id.trim_matches('/').split('/').collect();
+        let [subscriptions, subscription_id, groups, resource_group, providers, namespace, kind, name] = segments[..] else {
+            return None;
+        };
+
+        let expected = [
+            (subscriptions, "subscriptions"),
+            (groups, "resourceGroups"),
+            (providers, "providers"),
+            (namespace, "Microsoft.Compute"),
+            (kind, "virtualMachines")
+        ];
+        let valid = expected.iter().all(|(segment, key)| segment.eq_ignore_ascii_case(key))
+            && [subscription_id, resource_group, name].iter().all(|value| !value.is_empty());
+
+        valid.then(|| Self {
+            subscription_id: subscription_id.to_owned(),
+            resource_group: resource_group.to_owned(),
+            name: name.to_owned()
+        })
+    }
+}
+
+/// Criteria used to narrow down VM listings.
+#[derive(Debug, Default, Clone)]
+pub struct VmFilter {
+    pub locations: Vec<String>,
+
+    /// Tags that must all be present with exactly these values.
+    pub tags: Vec<(String, String)>,
+
+    /// Stops listing once this many VMs have matched.
+    pub limit: Option<usize>,
+
+    /// The power state VMs must be in, checked once instance views are known.
+    pub state: Option<StateFilter>
+}
+
+/// Power states that `vm list` can be narrowed down to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StateFilter {
+    Running,
+    /// Stopped or deallocated.
+    Stopped
+}
+
+impl StateFilter {
+    pub fn from_flags(running_only: bool, stopped_only: bool) -> Option<Self> {
+        match (running_only, stopped_only) {
+            (true, _) => Some(StateFilter::Running),
+            (_, true) => Some(StateFilter::Stopped),
+            _ => None
+        }
+    }
+}
+
+impl VmFilter {
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty() && self.tags.is_empty() && self.state.is_none()
+    }
+
+    /// Whether `count` matching VMs are enough to stop listing.
+    pub fn is_satisfied(&self, count: usize) -> bool {
+        self.limit.is_some_and(|limit| count >= limit)
+    }
+
+    pub fn matches(&self, vm: &VirtualMachine) -> bool {
+        self.matches_resource(&vm.resource.location, vm.resource.tags.as_ref())
+    }
+
+    /// Applies the power state criterion. VMs without an instance view only
+    /// match when there is no such criterion.
+    pub fn matches_state(&self, vm: &VirtualMachine) -> bool {
+        let Some(state) = self.state else {
+            return true;
+        };
+
+        let power = vm.properties.as_ref()
+            .and_then(|properties| properties.instance_view.as_ref())
+            .map(|view| power_state(view));
+
+        match (state, power.as_deref()) {
+            (StateFilter::Running, Some("VM running")) => true,
+            (StateFilter::Stopped, Some("VM stopped" | "VM deallocated")) => true,
+            _ => false
+        }
+    }
+
+    /// Applies the location and tag criteria to any resource, such as a
+    /// resource group.
+    pub fn matches_resource(&self, location: &str, tags: Option<&Value>) -> bool {
+        let location = self.locations.is_empty() || self.locations.iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(location));
+
+        let tags = self.tags.iter().all(|(key, value)| tags
+            .and_then(|tags| tags.get(key))
+            .and_then(|tag| tag.as_str())
+            .is_some_and(|tag| tag == value));
+
+        location && tags
+    }
+}
+
+/// How VM listings are ordered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum VmSort {
+    Name,
+    Location,
+    /// Running VMs first, then those in transition, then stopped ones.
+    Status,
+    Group
+}
+
+impl VmSort {
+    pub fn sort(self, vms: &mut [VirtualMachine], reverse: bool) {
+        match self {
+            VmSort::Name => vms.sort_by_cached_key(|vm| vm.resource.name.as_deref().unwrap_or_default().to_lowercase()),
+            VmSort::Location => vms.sort_by_cached_key(|vm| vm.resource.location.to_lowercase()),
+            VmSort::Status => vms.sort_by_cached_key(status_rank),
+            VmSort::Group => vms.sort_by_cached_key(|vm| vm.resource.id.as_deref()
+                .and_then(ResourceId::parse)
+                .map(|id| id.resource_group.to_lowercase())
+                .unwrap_or_default())
+        }
+
+        if reverse {
+            vms.reverse();
+        }
+    }
+}
+
+fn status_rank(vm: &VirtualMachine) -> u8 {
+    let status = vm.properties.as_ref()
+        .and_then(|properties| properties.instance_view.as_ref())
+        .map(power_state);
+
+    match status.as_deref() {
+        Some("VM running") => 0,
+        Some("VM starting") => 1,
+        Some("VM stopping" | "VM deallocating") => 2,
+        Some("VM stopped" | "VM deallocated") => 3,
+        _ => 4
+    }
+}
+
+/// Where a VM stands while waiting for it to reach a power state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    Complete,
+    Pending,
+    /// The instance view has no `PowerState/*` status, e.g. while the VM is
+    /// still provisioning. Holds the status codes it did report.
+    NoPowerState(Vec<String>),
+    /// Not checked because Azure throttled the requests even after retrying,
+    /// so the caller should back off before polling again.
+    Throttled
+}
+
+/// The VM operations `send_vm_command` relies on, so that it can be run
+/// against something other than Azure.
+#[async_trait]
+pub trait VmOperations {
+    /// Sends `command` to each VM, returning the VMs that rejected it.
+    async fn command(&self, vm_names: &[String], group_name: &str, subscription_id: &str, command: VmCommand) -> Vec<(String, AppError)>;
+
+    /// The VMs that are already in `state`.
+    async fn is_complete(&self, vm_names: &[String], group_name: &str, subscription_id: &str, state: &str) -> Result<Vec<String>, AppError>;
+
+    /// Reports how far each VM has got towards `state`.
+    async fn check_progress(&self, vm_names: &[String], group_name: &str, subscription_id: &str, state: &str) -> Result<Vec<(String, Progress)>, AppError>;
 }
 
 pub struct VmClient {
-    client: Client
+    client: Client,
+    network: NetworkClient,
+    max_in_flight: usize,
+    concurrency: usize
 }
 
 impl VmClient {
+    /// Resolves an image alias such as `Ubuntu2204`, ignoring case, to the
+    /// marketplace image it stands for.
+    pub fn resolve_image_alias(alias: &str) -> Option<ImageReference> {
+        let (_, urn) = IMAGE_ALIASES.iter().find(|(name, _)| name.eq_ignore_ascii_case(alias))?;
+        let [publisher, offer, sku, version] = urn.split(':').collect::<Vec<_>>()[..] else {
+            return None;
+        };
+
+        Some(ImageReference {
+            publisher: Some(publisher.to_owned()),
+            offer: Some(offer.to_owned()),
+            sku: Some(sku.to_owned()),
+            version: Some(version.to_owned()),
+            ..Default::default()
+        })
+    }
+
     pub fn new(creds: Arc<dyn TokenCredential>) -> Self {
-        let client = Client::builder(creds)
-            .retry(RetryOptions::exponential(ExponentialRetryOptions::default()))
+        let client = Client::builder(creds.clone())
+            .retry(retry::options())
+            .transport(api_version::transport(Service::Compute))
+            .build();
+
+        let network = NetworkClient::builder(creds)
+            .retry(retry::options())
+            .transport(api_version::transport(Service::Network))
             .build();
 
         Self {
-            client
+            client,
+            network,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            concurrency: DEFAULT_CONCURRENCY
         }
     }
 
-    pub async fn get_instance_view(&self, vm_name: &str, group_name: &str, subscription_id: &str) -> Result<VirtualMachineInstanceView, Box<dyn std::error::Error>> {
-        let instance_view = self.client.virtual_machines_client()
-            .instance_view(group_name, vm_name, subscription_id)
-            .await?;
+    /// Sets how many power state requests may be in flight at once.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Sets how many Azure requests of any kind may be in flight at once.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// The number of power state requests to dispatch at once, which never
+    /// exceeds the overall concurrency.
+    fn in_flight(&self) -> usize {
+        self.max_in_flight.min(self.concurrency)
+    }
+
+    pub async fn get_instance_view(&self, vm_name: &str, group_name: &str, subscription_id: &str) -> Result<VirtualMachineInstanceView, AppError> {
+        info!("Getting instance view of {vm_name}");
+        let instance_view = timings::time("instance view", self.client.virtual_machines_client()
+            .instance_view(group_name, vm_name, subscription_id))
+            .await
+            .map_err(AppError::or_not_found("virtual machine", vm_name))?;
 
         Ok(instance_view)
     }
 
-    pub async fn is_complete<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str, state: &str) -> Result<Vec<T>, Box<dyn std::error::Error>>
+    pub async fn is_complete<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str, state: &str) -> Result<Vec<T>, AppError>
     where
         T: AsRef<str>,
         I: IntoIterator<Item = T>
     {
-        let mut complete = Vec::<T>::new();
-        for vm_name in vm_names.into_iter() {
-            let view = self.get_instance_view(vm_name.as_ref(), group_name, subscription_id).await?;
+        let complete = self.check_progress(vm_names, group_name, subscription_id, state)
+            .await?
+            .into_iter()
+            .filter(|(_, progress)| matches!(progress, Progress::Complete))
+            .map(|(vm_name, _)| vm_name)
+            .collect();
 
-            let status = view.statuses.iter()
-                .filter(|s| s.code.as_deref().is_some_and(|c| c.contains("PowerState")))
-                .map(|s| s.display_status.as_deref().unwrap_or_else(|| "Unknown"))
-                .nth(0)
-                .unwrap_or_else(|| "Unknown");
+        Ok(complete)
+    }
 
-            if status.contains(state) {
-                complete.push(vm_name);
+    /// Reports how far each VM has got towards `state`.
+    pub async fn check_progress<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str, state: &str) -> Result<Vec<(T, Progress)>, AppError>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>
+    {
+        let mut progress = Vec::new();
+        let mut throttled = false;
+        for vm_name in vm_names.into_iter() {
+            if throttled {
+                progress.push((vm_name, Progress::Throttled));
+                continue;
             }
+
+            let view = match self.get_instance_view(vm_name.as_ref(), group_name, subscription_id).await {
+                Ok(view) => view,
+                Err(e) if e.is_throttled() => {
+                    warn!("Throttled while checking {}: {e}", vm_name.as_ref());
+                    throttled = true;
+                    progress.push((vm_name, Progress::Throttled));
+                    continue;
+                },
+                Err(e) => return Err(e)
+            };
+
+            let has_power_state = view.statuses.iter()
+                .filter_map(|status| status.code.as_deref())
+                .any(|code| code.get(..11).is_some_and(|prefix| prefix.eq_ignore_ascii_case("PowerState/")));
+
+            let vm_progress = if power_state(&view) == state {
+                Progress::Complete
+            } else if has_power_state {
+                Progress::Pending
+            } else {
+                Progress::NoPowerState(view.statuses.iter().filter_map(|status| status.code.clone()).collect())
+            };
+            progress.push((vm_name, vm_progress));
         }
-        Ok(complete)
+        Ok(progress)
     }
 
-    pub async fn get_vm(&self, vm_name: &str, group_name: &str, subscription_id: &str) -> Result<VirtualMachine, Box<dyn std::error::Error>> {
-        let vm = self.client.virtual_machines_client()
-            .get(group_name, vm_name, subscription_id)
-            .await?;
+    /// Fetches only the power state of each VM, without the full VM models.
+    pub async fn get_power_states<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str) -> Result<Vec<VmStatus>, AppError>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>
+    {
+        stream::iter(vm_names)
+            .map(|vm_name| async move {
+                let view = self.get_instance_view(vm_name.as_ref(), group_name, subscription_id).await?;
+                Ok::<_, AppError>(VmStatus {
+                    name: vm_name.as_ref().to_owned(),
+                    status: power_state(&view).into_owned()
+                })
+            })
+            .buffered(self.concurrency)
+            .try_collect()
+            .await
+    }
+
+    pub async fn get_vm(&self, vm_name: &str, group_name: &str, subscription_id: &str) -> Result<VirtualMachine, AppError> {
+        info!("Getting VM {vm_name} in {group_name}");
+        let vm = timings::time("get VM", self.client.virtual_machines_client()
+            .get(group_name, vm_name, subscription_id))
+            .await
+            .map_err(AppError::or_not_found("virtual machine", vm_name))?;
 
         Ok(vm)
     }
-    pub async fn get_vm_with_instance_view(&self, vm_name: &str, group_name: &str, subscription_id: &str) -> Result<VirtualMachine, Box<dyn std::error::Error>> {
+    pub async fn get_vm_with_instance_view(&self, vm_name: &str, group_name: &str, subscription_id: &str) -> Result<(VirtualMachine, NetworkAddresses), AppError> {
         let mut vm = self.get_vm(vm_name, group_name, subscription_id)
             .await?;
 
@@ -71,26 +461,301 @@ impl VmClient {
             .get_instance_view(vm_name, group_name, subscription_id)
             .await?;
 
+        let addresses = self.get_network_addresses(&vm).await?;
+
         let properties = vm.properties.get_or_insert(VirtualMachineProperties::default());
         properties.instance_view = Some(instance_view);
 
-        Ok(vm)
+        Ok((vm, addresses))
     }
 
-    pub async fn list_vms(&self, group_name: &str, subscription_id: &str) -> Result<Vec<VirtualMachine>, Box<dyn std::error::Error>> {
-        let vms: Vec<VirtualMachine> = self.client.virtual_machines_client()
-            .list(group_name, subscription_id)
+    /// The IDs of every NIC attached to the VM.
+    fn nic_ids(vm: &VirtualMachine) -> Vec<ResourceId> {
+        vm.properties.as_ref()
+            .and_then(|properties| properties.network_profile.as_ref())
+            .map(|profile| profile.network_interfaces.iter()
+                .filter_map(|nic| nic.sub_resource.id.as_deref())
+                .filter_map(ResourceId::parse)
+                .collect())
+            .unwrap_or_default()
+    }
+
+    async fn get_nic(&self, nic_id: &ResourceId) -> Result<NetworkInterface, AppError> {
+        info!("Getting network interface {}", nic_id.name);
+        let nic = timings::time("get network interface", self.network.network_interfaces_client()
+            .get(&nic_id.resource_group, &nic_id.name, &nic_id.subscription_id))
+            .await?;
+
+        Ok(nic)
+    }
+
+    /// Collects the private and public IPs of every NIC attached to the VM.
+    pub async fn get_network_addresses(&self, vm: &VirtualMachine) -> Result<NetworkAddresses, AppError> {
+        let mut addresses = NetworkAddresses::default();
+        for nic_id in Self::nic_ids(vm) {
+            let nic = self.get_nic(&nic_id).await?;
+
+            let configs = nic.properties.iter()
+                .flat_map(|properties| properties.ip_configurations.iter())
+                .filter_map(|config| config.properties.as_ref());
+
+            for config in configs {
+                if let Some(ip) = config.private_ip_address.as_ref() {
+                    addresses.private.push(ip.clone());
+                }
+
+                let public_id = config.public_ip_address.as_ref()
+                    .and_then(|public| public.resource.id.as_deref())
+                    .and_then(ResourceId::parse);
+
+                if let Some(public_id) = public_id {
+                    info!("Getting public IP {}", public_id.name);
+                    let public = timings::time("get public IP", self.network.public_ip_addresses_client()
+                        .get(&public_id.resource_group, &public_id.name, &public_id.subscription_id))
+                        .await?;
+
+                    if let Some(ip) = public.properties.and_then(|properties| properties.ip_address) {
+                        addresses.public.push(ip);
+                    }
+                }
+            }
+        }
+        Ok(addresses)
+    }
+
+    /// Looks up each disk attached to the VM, OS disk first, to report how it
+    /// is encrypted. Unmanaged disks have no disk resource to look up, so
+    /// they're listed without one.
+    pub async fn get_disks(&self, vm: &VirtualMachine) -> Result<Vec<VmDisk>, AppError> {
+        let properties = vm.properties.as_ref();
+        let encryption_at_host = properties
+            .and_then(|properties| properties.security_profile.as_ref())
+            .and_then(|profile| profile.encryption_at_host)
+            .unwrap_or_default();
+
+        let Some(storage) = properties.and_then(|properties| properties.storage_profile.as_ref()) else {
+            return Ok(Vec::new());
+        };
+
+        let os_disk = storage.os_disk.iter()
+            .map(|disk| (None, disk.name.as_deref(), disk.managed_disk.as_ref(), disk.disk_size_gb));
+        let data_disks = storage.data_disks.iter()
+            .map(|disk| (Some(disk.lun), disk.name.as_deref(), disk.managed_disk.as_ref(), disk.disk_size_gb));
+
+        let mut disks = Vec::new();
+        for (lun, name, managed_disk, size_gb) in os_disk.chain(data_disks) {
+            let disk_id = managed_disk
+                .and_then(|managed_disk| managed_disk.sub_resource.id.as_deref())
+                .and_then(ResourceId::parse);
+
+            let disk = match disk_id {
+                Some(disk_id) => Some(self.get_disk(&disk_id).await?),
+                None => None
+            };
+
+            disks.push(VmDisk {
+                name: name.map(str::to_owned)
+                    .or_else(|| disk.as_ref().and_then(|disk| disk.resource.name.clone()))
+                    .unwrap_or_default(),
+                lun,
+                size_gb: disk.as_ref()
+                    .and_then(|disk| disk.properties.as_ref())
+                    .and_then(|properties| properties.disk_size_gb)
+                    .or(size_gb),
+                sku: disk.as_ref()
+                    .and_then(|disk| disk.sku.as_ref())
+                    .and_then(|sku| serde_json::to_value(&sku.name).ok())
+                    .and_then(|sku| sku.as_str().map(str::to_owned)),
+                encryption: disk.as_ref().map(disk_encryption),
+                encryption_at_host
+            });
+        }
+        Ok(disks)
+    }
+
+    async fn get_disk(&self, disk_id: &ResourceId) -> Result<Disk, AppError> {
+        info!("Getting disk {}", disk_id.name);
+        let disk = timings::time("get disk", self.client.disks_client()
+            .get(&disk_id.subscription_id, &disk_id.resource_group, &disk_id.name))
+            .await?;
+        Ok(disk)
+    }
+
+    /// Collects the inbound allow rules guarding each NIC of the VM. A NIC
+    /// without an NSG of its own falls back to the NSG of its primary subnet.
+    pub async fn get_nsg_rules(&self, vm: &VirtualMachine) -> Result<Vec<NicSecurity>, AppError> {
+        let mut nics = Vec::new();
+        for nic_id in Self::nic_ids(vm) {
+            let nic = self.get_nic(&nic_id).await?;
+            let properties = nic.properties.as_ref();
+
+            let own_nsg = properties
+                .and_then(|properties| properties.network_security_group.as_ref())
+                .and_then(|nsg| nsg.resource.id.clone());
+
+            let (nsg_id, from_subnet) = match own_nsg {
+                Some(id) => (Some(id), false),
+                None => {
+                    let configs: Vec<_> = properties.iter()
+                        .flat_map(|properties| properties.ip_configurations.iter())
+                        .filter_map(|config| config.properties.as_ref())
+                        .collect();
+
+                    let subnet_id = configs.iter()
+                        .find(|config| config.primary == Some(true))
+                        .or_else(|| configs.first())
+                        .and_then(|config| config.subnet.as_ref())
+                        .and_then(|subnet| subnet.sub_resource.id.as_deref());
+
+                    match subnet_id {
+                        Some(subnet_id) => (self.get_subnet_nsg_id(subnet_id).await?, true),
+                        None => (None, false)
+                    }
+                }
+            };
+
+            let (nsg, rules) = match nsg_id.as_deref().and_then(ResourceId::parse) {
+                Some(nsg_id) => {
+                    info!("Getting network security group {}", nsg_id.name);
+                    let nsg = timings::time("get network security group", self.network.network_security_groups_client()
+                        .get(&nsg_id.resource_group, &nsg_id.name, &nsg_id.subscription_id))
+                        .await?;
+
+                    let mut rules: Vec<NsgRule> = nsg.properties.iter()
+                        .flat_map(|properties| properties.security_rules.iter().chain(&properties.default_security_rules))
+                        .filter_map(inbound_allow_rule)
+                        .collect();
+                    rules.sort_by_key(|rule| rule.priority);
+
+                    (Some(nsg_id.name), rules)
+                },
+                None => (None, Vec::new())
+            };
+
+            let from_subnet = from_subnet && nsg.is_some();
+            nics.push(NicSecurity { nic: nic_id.name, nsg, from_subnet, rules });
+        }
+        Ok(nics)
+    }
+
+    /// The ID of the NSG attached to a subnet, if any.
+    async fn get_subnet_nsg_id(&self, subnet_id: &str) -> Result<Option<String>, AppError> {
+        let (Some(id), Some(network)) = (ResourceId::parse(subnet_id), ResourceId::segment_after(subnet_id, "virtualNetworks")) else {
+            return Ok(None);
+        };
+
+        info!("Getting subnet {} of {network}", id.name);
+        let subnet = timings::time("get subnet", self.network.subnets_client()
+            .get(&id.resource_group, network, &id.name, &id.subscription_id))
+            .await?;
+
+        Ok(subnet.properties
+            .and_then(|properties| properties.network_security_group)
+            .and_then(|nsg| nsg.resource.id))
+    }
+
+    /// Fetches SAS URLs for the VM's console screenshot and serial log.
+    pub async fn get_boot_diagnostics(&self, vm_name: &str, group_name: &str, subscription_id: &str) -> Result<RetrieveBootDiagnosticsDataResult, AppError> {
+        let vm = self.get_vm(vm_name, group_name, subscription_id).await?;
+        let enabled = vm.properties.as_ref()
+            .and_then(|properties| properties.diagnostics_profile.as_ref())
+            .and_then(|profile| profile.boot_diagnostics.as_ref())
+            .and_then(|diagnostics| diagnostics.enabled)
+            .unwrap_or(false);
+
+        if !enabled {
+            return Err(AppError::BootDiagnosticsDisabled(vm_name.to_owned()));
+        }
+
+        info!("Retrieving boot diagnostics of {vm_name}");
+        let data = timings::time("boot diagnostics", self.client.virtual_machines_client()
+            .retrieve_boot_diagnostics_data(group_name, vm_name, subscription_id))
+            .await?;
+
+        Ok(data)
+    }
+
+    /// Changes the size of a VM. With `wait`, waits for the update to complete
+    /// and returns the updated VM, otherwise returns `None` once Azure has
+    /// accepted it.
+    pub async fn resize_vm(&self, vm_name: &str, size: &str, group_name: &str, subscription_id: &str, wait: bool) -> Result<Option<VirtualMachine>, AppError> {
+        info!("Listing available sizes for {vm_name}");
+        let available: Vec<String> = timings::time("list sizes", self.client.virtual_machines_client()
+            .list_available_sizes(group_name, vm_name, subscription_id)
             .into_stream()
-            .try_collect::<Vec<_>>()
+            .try_collect::<Vec<_>>())
             .await?
             .into_iter()
-            .flat_map(|vms| vms.value)
+            .flat_map(|sizes| sizes.value)
+            .filter_map(|size| size.name)
             .collect();
 
-        Ok(vms)
+        if !available.iter().any(|name| name.eq_ignore_ascii_case(size)) {
+            return Err(AppError::SizeUnavailable { size: size.to_owned(), available });
+        }
+
+        let mut vm = self.get_vm(vm_name, group_name, subscription_id).await?;
+
+        // VmSize is an open enum, so let serde map known names and keep unknown ones.
+        let vm_size = serde_json::from_value(serde_json::Value::from(size))?;
+        let properties = vm.properties.get_or_insert(VirtualMachineProperties::default());
+        properties.hardware_profile.get_or_insert(HardwareProfile::default()).vm_size = Some(vm_size);
+
+        info!("Resizing {vm_name} to {size}");
+        let request = self.client.virtual_machines_client()
+            .create_or_update(group_name, vm_name, vm, subscription_id);
+
+        if !wait {
+            timings::time("resize VM", request.send()).await?;
+            return Ok(None);
+        }
+
+        let vm = timings::time("resize VM", request).await?;
+        Ok(Some(vm))
+    }
+
+    /// Adds and removes tags on a VM with a tags-only PATCH, returning the
+    /// updated VM.
+    pub async fn update_tags(&self, vm_name: &str, add: &[(String, String)], remove: &[String], group_name: &str, subscription_id: &str) -> Result<VirtualMachine, AppError> {
+        let vm = self.get_vm(vm_name, group_name, subscription_id).await?;
+
+        let mut tags = match vm.resource.tags {
+            Some(Value::Object(tags)) => tags,
+            _ => Map::new()
+        };
+        for key in remove {
+            tags.remove(key);
+        }
+        for (key, value) in add {
+            tags.insert(key.clone(), Value::from(value.as_str()));
+        }
+
+        let mut update = VirtualMachineUpdate::new();
+        update.update_resource.tags = Some(Value::Object(tags));
+
+        info!("Updating tags of {vm_name}");
+        let vm = timings::time("update VM tags", self.client.virtual_machines_client()
+            .update(group_name, vm_name, update, subscription_id))
+            .await?;
+
+        Ok(vm)
+    }
+
+    pub async fn list_vms(&self, group_name: &str, subscription_id: &str) -> Result<Vec<VirtualMachine>, AppError> {
+        timings::time("list VMs", self.list_vm_pages(group_name, subscription_id).try_concat()).await
     }
 
-    pub async fn list_vm_names(&self, group_name: &str, subscription_id: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    /// Streams the VMs in a group a page at a time.
+    pub fn list_vm_pages(&self, group_name: &str, subscription_id: &str) -> impl Stream<Item = Result<Vec<VirtualMachine>, AppError>> {
+        info!("Listing VMs in {group_name}");
+        self.client.virtual_machines_client()
+            .list(group_name, subscription_id)
+            .into_stream()
+            .map_ok(|vms| vms.value)
+            .map_err(AppError::from)
+    }
+
+    pub async fn list_vm_names(&self, group_name: &str, subscription_id: &str) -> Result<Vec<String>, AppError> {
         let names: Vec<String> = self.list_vms(group_name, subscription_id)
             .await?
             .into_iter()
@@ -100,73 +765,205 @@ impl VmClient {
         Ok(names)
     }
 
-    pub async fn list_all_vms(&self, subscription_id: &str) -> Result<Vec<VirtualMachine>, Box<dyn std::error::Error>> {
-        let vms: Vec<VirtualMachine> = self.client.virtual_machines_client()
+    /// Streams the VMs in a subscription a page at a time, so callers can
+    /// report progress before the whole listing has arrived.
+    pub fn list_all_vm_pages(&self, subscription_id: &str) -> impl Stream<Item = Result<Vec<VirtualMachine>, AppError>> {
+        info!("Listing all VMs in {subscription_id}");
+        self.client.virtual_machines_client()
             .list_all(subscription_id)
             .status_only("true")
             .into_stream()
-            .try_collect::<Vec<_>>()
-            .await?
-            .into_iter()
-            .flat_map(|vms| vms.value)
-            .collect();
-
-        Ok(vms)
+            .map_ok(|vms| vms.value)
+            .map_err(AppError::from)
     }
 
-    pub async fn list_vms_with_instance_view(&self, group_name: &str, subscription_id: &str) -> Result<Vec<VirtualMachine>, Box<dyn std::error::Error>> {
-        let mut vms = self.list_vms(group_name, subscription_id).await?;
+    /// Lists the VMs in a group that match `filter`, along with their instance views.
+    pub async fn list_vms_with_instance_view(&self, group_name: &str, subscription_id: &str, filter: &VmFilter) -> Result<Vec<VirtualMachine>, AppError> {
+        // Only page through as much of the group as the filter's limit needs,
+        // unless the power state has to be known before counting a VM.
+        let mut vms = Vec::new();
+        let mut pages = pin!(self.list_vm_pages(group_name, subscription_id));
+        while let Some(page) = pages.try_next().await? {
+            vms.extend(page.into_iter().filter(|vm| filter.matches(vm)));
+            if filter.state.is_none() && filter.is_satisfied(vms.len()) {
+                break;
+            }
+        }
+        if filter.state.is_none() {
+            vms.truncate(filter.limit.unwrap_or(usize::MAX));
+        }
 
-        for vm in vms.iter_mut().filter(|vm| vm.resource.name.is_some()) {
-            let name = vm.resource.name.as_deref().unwrap();
+        let names: Vec<String> = vms.iter()
+            .filter_map(|vm| vm.resource.name.clone())
+            .collect();
 
-            let instance_view = self
-                .get_instance_view(name, group_name, subscription_id)
-                .await?;
+        let mut views: HashMap<String, VirtualMachineInstanceView> = stream::iter(names)
+            .map(|name| async move {
+                let view = self.get_instance_view(&name, group_name, subscription_id).await;
+                (name, view)
+            })
+            .buffer_unordered(self.concurrency)
+            .filter_map(|(name, view)| async move {
+                match view {
+                    Ok(view) => Some((name, view)),
+                    Err(e) => {
+                        warn!("Failed to get instance view for {name}: {e}");
+                        None
+                    }
+                }
+            })
+            .collect()
+            .await;
 
-            let properties = vm.properties.get_or_insert(VirtualMachineProperties::default());
-            properties.instance_view = Some(instance_view);
+        for vm in vms.iter_mut() {
+            let view = vm.resource.name.as_ref().and_then(|name| views.remove(name));
+            if let Some(instance_view) = view {
+                let properties = vm.properties.get_or_insert(VirtualMachineProperties::default());
+                properties.instance_view = Some(instance_view);
+            }
         }
+
+        vms.retain(|vm| filter.matches_state(vm));
+        vms.truncate(filter.limit.unwrap_or(usize::MAX));
         Ok(vms)
     }
 
-    pub async fn command<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str, command: VmCommand) -> Result<(), Box<dyn std::error::Error>>
+    /// Sends `command` to every VM, returning the VMs whose request was
+    /// rejected rather than stopping at the first failure.
+    pub async fn command<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str, command: VmCommand) -> Vec<(String, AppError)>
         where
             T: AsRef<str>,
             I: IntoIterator<Item = T>
     {
-        match command {
-            VmCommand::Start => self.start_vms(vm_names, group_name, subscription_id).await,
-            VmCommand::Stop => self.stop_vms(vm_names, group_name, subscription_id).await
-        }
+        let (progress, operation) = match command {
+            VmCommand::Start => ("Starting", "start VM"),
+            VmCommand::Stop => ("Deallocating", "deallocate VM"),
+            VmCommand::PowerOff => ("Powering off", "power off VM"),
+            VmCommand::Restart => ("Restarting", "restart VM")
+        };
+
+        stream::iter(vm_names)
+            .map(|vm_name| async move {
+                let vm_name = vm_name.as_ref();
+                info!("{progress} {vm_name}");
+
+                let vms = self.client.virtual_machines_client();
+                timings::time(operation, async {
+                    match command {
+                        VmCommand::Start => vms.start(group_name, vm_name, subscription_id).send().await.map(|_| ()),
+                        VmCommand::Stop => vms.deallocate(group_name, vm_name, subscription_id).send().await.map(|_| ()),
+                        // Stops without deallocating, so VMs keep their compute
+                        // and dynamic IPs (and keep being billed for them).
+                        VmCommand::PowerOff => vms.power_off(group_name, vm_name, subscription_id).send().await.map(|_| ()),
+                        VmCommand::Restart => vms.restart(group_name, vm_name, subscription_id).send().await.map(|_| ())
+                    }
+                })
+                    .await
+                    .err()
+                    .map(|e| (vm_name.to_owned(), AppError::from(e)))
+            })
+            .buffer_unordered(self.in_flight())
+            .filter_map(|failure| async move { failure })
+            .collect()
+            .await
     }
+}
 
-    pub async fn start_vms<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str) -> Result<(), Box<dyn std::error::Error>>
-    where
-        T: AsRef<str>,
-        I: IntoIterator<Item = T>
-    {
-        for vm_name in vm_names.into_iter() {
-            self.client.virtual_machines_client()
-                .start(group_name, vm_name.as_ref(), subscription_id)
-                .send()
-                .await?;
-        }
-        Ok(())
+#[async_trait]
+impl VmOperations for VmClient {
+    async fn command(&self, vm_names: &[String], group_name: &str, subscription_id: &str, command: VmCommand) -> Vec<(String, AppError)> {
+        VmClient::command(self, vm_names, group_name, subscription_id, command).await
     }
 
-    pub async fn stop_vms<I, T>(&self, vm_names: I, group_name: &str, subscription_id: &str) -> Result<(), Box<dyn std::error::Error>>
-        where
-            T: AsRef<str>,
-            I: IntoIterator<Item = T>
-    {
-        for vm_name in vm_names.into_iter() {
-            self.client.virtual_machines_client()
-                .deallocate(group_name, vm_name.as_ref(), subscription_id)
-                .send()
-                .await?;
-        }
-        Ok(())
+    async fn is_complete(&self, vm_names: &[String], group_name: &str, subscription_id: &str, state: &str) -> Result<Vec<String>, AppError> {
+        let complete = VmClient::is_complete(self, vm_names, group_name, subscription_id, state).await?;
+        Ok(complete.into_iter().cloned().collect())
+    }
+
+    async fn check_progress(&self, vm_names: &[String], group_name: &str, subscription_id: &str, state: &str) -> Result<Vec<(String, Progress)>, AppError> {
+        let progress = VmClient::check_progress(self, vm_names, group_name, subscription_id, state).await?;
+        Ok(progress.into_iter().map(|(name, progress)| (name.clone(), progress)).collect())
     }
 }
 
+/// Flattens a security rule for display if it allows inbound traffic.
+fn inbound_allow_rule(rule: &SecurityRule) -> Option<NsgRule> {
+    let properties = rule.properties.as_ref()?;
+    if !matches!(properties.direction, SecurityRuleDirection::Inbound) || !matches!(properties.access, SecurityRuleAccess::Allow) {
+        return None;
+    }
+
+    let either = |single: &Option<String>, many: &[String]| match single {
+        Some(value) => value.clone(),
+        None => many.join(", ")
+    };
+
+    Some(NsgRule {
+        name: rule.name.clone().unwrap_or_default(),
+        priority: properties.priority,
+        protocol: serde_json::to_value(&properties.protocol).ok()
+            .and_then(|protocol| protocol.as_str().map(str::to_owned))
+            .unwrap_or_default(),
+        ports: either(&properties.destination_port_range, &properties.destination_port_ranges),
+        source: either(&properties.source_address_prefix, &properties.source_address_prefixes),
+        action: "Allow".to_owned()
+    })
+}
+
+/// The keys a managed disk is encrypted with. Azure encrypts every managed
+/// disk, so a disk without an encryption type uses platform-managed keys.
+fn disk_encryption(disk: &Disk) -> DiskEncryption {
+    let kind = disk.properties.as_ref()
+        .and_then(|properties| properties.encryption.as_ref())
+        .and_then(|encryption| serde_json::to_value(&encryption.type_).ok())
+        .and_then(|kind| kind.as_str().map(str::to_owned));
+
+    match kind.as_deref() {
+        None | Some("EncryptionAtRestWithPlatformKey") => DiskEncryption::PlatformKey,
+        Some("EncryptionAtRestWithCustomerKey") => DiskEncryption::CustomerKey,
+        Some("EncryptionAtRestWithPlatformAndCustomerKeys") => DiskEncryption::PlatformAndCustomerKeys,
+        Some(kind) => DiskEncryption::Other(kind.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_image_aliases_ignoring_case() {
+        let image = VmClient::resolve_image_alias("ubuntu2204").unwrap();
+        assert_eq!(image.publisher.as_deref(), Some("Canonical"));
+        assert_eq!(image.offer.as_deref(), Some("0001-com-ubuntu-server-jammy"));
+        assert_eq!(image.sku.as_deref(), Some("22_04-lts-gen2"));
+        assert_eq!(image.version.as_deref(), Some("latest"));
+
+        assert!(VmClient::resolve_image_alias("Ubuntu1404").is_none());
+        assert!(IMAGE_ALIASES.iter().all(|(alias, _)| VmClient::resolve_image_alias(alias).is_some()));
+    }
+
+    #[test]
+    fn parses_resource_ids() {
+        let web01 = ResourceId { subscription_id: "sub".to_owned(), resource_group: "rg-web".to_owned(), name: "web01".to_owned() };
+        let ids = [
+            "/subscriptions/sub/resourceGroups/rg-web/providers/Microsoft.Compute/virtualMachines/web01",
+            "/SUBSCRIPTIONS/sub/resourcegroups/rg-web/providers/microsoft.compute/VirtualMachines/web01",
+            "/subscriptions/sub/resourceGroups/rg-web/providers/Microsoft.Compute/virtualMachines/web01/"
+        ];
+        for id in ids {
+            assert_eq!(ResourceId::parse_vm(id).as_ref(), Some(&web01), "{id}");
+            assert_eq!(ResourceId::parse(id).as_ref(), Some(&web01), "{id}");
+        }
+
+        let nic = "/subscriptions/sub/resourceGroups/rg-web/providers/Microsoft.Network/networkInterfaces/web01-nic";
+        assert_eq!(ResourceId::parse_vm(nic), None);
+        assert_eq!(ResourceId::parse(nic).map(|id| id.name), Some("web01-nic".to_owned()));
+        assert_eq!(ResourceId::segment_after(nic, "NetworkInterfaces"), Some("web01-nic"));
+        assert_eq!(ResourceId::segment_after(nic, "virtualNetworks"), None);
+        assert_eq!(ResourceId::parse_vm(&format!("{}/extensions/agent", ids[0])), None);
+
+        let no_group = "/subscriptions/sub/providers/Microsoft.Compute/virtualMachines/web01";
+        assert_eq!(ResourceId::parse_vm(no_group), None);
+        assert_eq!(ResourceId::parse(no_group), None);
+    }
+}
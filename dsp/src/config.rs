@@ -0,0 +1,64 @@
+use tabled::Tabled;
+use serde::Serialize;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::{DisplayOptions, Output, emit, get_style, print_serialized, tabulate};
+
+/// The settings stored for the active profile.
+#[derive(Debug, Serialize)]
+pub struct Config<'a> {
+    pub profile: &'a str,
+    pub subscription_id: Option<&'a str>,
+    pub resource_group: Option<&'a str>,
+    pub vault_name: Option<&'a str>,
+    pub vault_resource_group: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub api_versions: BTreeMap<&'a str, &'a str>
+}
+
+pub fn display_config(config: &Config, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Single(config), opts);
+    }
+
+    let api_versions = (!config.api_versions.is_empty()).then(|| config.api_versions.iter()
+        .map(|(service, version)| format!("{service}={version}"))
+        .collect::<Vec<_>>()
+        .join(", "));
+
+    let rows = [
+        Row("Profile", Some(config.profile)),
+        Row("Subscription ID", config.subscription_id),
+        Row("Resource Group", config.resource_group),
+        Row("Vault Name", config.vault_name),
+        Row("Vault Resource Group", config.vault_resource_group),
+        Row("API Versions", api_versions.as_deref())
+    ];
+
+    let Some(mut table) = tabulate(rows, opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+struct Row<'a>(&'static str, Option<&'a str>);
+
+impl<'a> Tabled for Row<'a> {
+    const LENGTH: usize = 2;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        vec![
+            Cow::from(self.0),
+            Cow::from(self.1.unwrap_or("<not set>"))
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Setting"),
+            Cow::from("Value")
+        ]
+    }
+}
@@ -1,4 +1,21 @@
-use tabled::settings::{style::{RawStyle, Style}, Color};
+use tabled::{Table, Tabled, settings::{style::{RawStyle, Style}, Color}};
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+pub mod config;
+pub use config::*;
+
+pub mod recovery;
+pub use recovery::*;
 
 pub mod rg;
 pub use rg::*;
@@ -6,6 +23,12 @@ pub use rg::*;
 pub mod sub;
 pub use sub::*;
 
+pub mod timing;
+pub use timing::*;
+
+pub mod vault;
+pub use vault::*;
+
 pub mod vm;
 pub use vm::*;
 
@@ -14,8 +37,182 @@ pub enum Output<'a, T> {
     Multiple(&'a [T])
 }
 
-pub(crate) fn get_style() -> RawStyle {
-    let mut style = RawStyle::from(Style::modern());
+impl<'a, T: Serialize> Serialize for Output<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Output::Single(item) => item.serialize(serializer),
+            Output::Multiple(items) => items.serialize(serializer)
+        }
+    }
+}
+
+/// Serializes a duration as fractional seconds rather than serde's
+/// `{"secs", "nanos"}` pair.
+pub(crate) fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// How the `display_*` functions render their output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+    /// A colored table for interactive use.
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    /// Comma-separated rows with the same columns as the table.
+    Csv
+}
+
+impl Format {
+    /// Whether output is the raw models rather than rows of columns.
+    pub fn is_serialized(self) -> bool {
+        matches!(self, Format::Json | Format::Yaml)
+    }
+}
+
+/// The borders drawn around tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum TableStyle {
+    /// Box-drawing borders, green when colors are on.
+    #[default]
+    Modern,
+    /// No borders at all.
+    Plain,
+    /// A GitHub-flavored Markdown table, never colored.
+    Markdown,
+    /// Borders drawn with `+`, `-` and `|`.
+    Ascii
+}
+
+/// Settings shared by all of the `display_*` functions.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    pub format: Format,
+    pub color: bool,
+    pub style: TableStyle,
+
+    /// The columns of VM tables, in order. Empty shows the default columns.
+    pub columns: Vec<VmColumn>,
+
+    /// Shows every VM column and disables truncating VM tables to the
+    /// terminal width.
+    pub wide: bool,
+
+    /// Suppresses progress output and the tables printed after changes.
+    pub quiet: bool,
+
+    /// Prints JSON on a single line instead of indenting it.
+    pub compact: bool,
+
+    /// Writes output to this file instead of printing it.
+    pub output_file: Option<OutputFile>
+}
+
+/// A file that output goes to instead of the terminal. It is only opened by
+/// the first write, so a command that fails before printing anything leaves
+/// an existing file as it was.
+#[derive(Debug, Clone)]
+pub struct OutputFile {
+    path: PathBuf,
+    /// Whether the next write replaces the file rather than appending to it.
+    /// Shared between clones so only the very first write truncates.
+    truncate: Arc<AtomicBool>
+}
+
+impl OutputFile {
+    pub fn new(path: PathBuf, truncate: bool) -> Self {
+        Self { path, truncate: Arc::new(AtomicBool::new(truncate)) }
+    }
+
+    fn open(&self) -> io::Result<File> {
+        let truncate = self.truncate.swap(false, Ordering::Relaxed);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .append(!truncate)
+            .open(&self.path)
+    }
+}
+
+impl DisplayOptions {
+    /// Whether spinners and other progress chatter should be shown.
+    pub fn show_progress(&self) -> bool {
+        self.format == Format::Table && !self.quiet
+    }
+
+    /// Whether cells such as VM power states should be colored.
+    pub(crate) fn color_cells(&self) -> bool {
+        self.color && self.style != TableStyle::Markdown
+    }
+}
+
+/// Prints the raw models in a machine-readable format.
+///
+/// The output goes through `serde_json::Value`, whose maps are ordered by key,
+/// so the same models always serialize identically.
+pub(crate) fn print_serialized<T: Serialize>(out: &Output<T>, opts: &DisplayOptions) -> io::Result<()> {
+    let value = serde_json::to_value(out)?;
+    let text = match opts.format {
+        Format::Json if opts.compact => serde_json::to_string(&value)?,
+        Format::Json => serde_json::to_string_pretty(&value)?,
+        Format::Yaml => serde_yaml::to_string(&value).map_err(io::Error::other)?,
+        Format::Table | Format::Csv => unreachable!("tables are rendered by the display functions")
+    };
+    emit(&text, opts)
+}
+
+/// Prints a rendered table or document, or appends it to the output file.
+pub(crate) fn emit(text: &dyn Display, opts: &DisplayOptions) -> io::Result<()> {
+    let Some(output_file) = opts.output_file.as_ref() else {
+        println!("{text}");
+        return Ok(());
+    };
+
+    writeln!(output_file.open()?, "{text}")
+}
+
+/// Builds a table from the rows, or prints them as CSV and returns `None`
+/// when that is the selected format.
+pub(crate) fn tabulate<R: Tabled>(rows: impl IntoIterator<Item = R>, opts: &DisplayOptions) -> io::Result<Option<Table>> {
+    if opts.format != Format::Csv {
+        return Ok(Some(Table::new(rows)));
+    }
+
+    let mut lines = vec![csv_line(R::headers())];
+    lines.extend(rows.into_iter().map(|row| csv_line(row.fields())));
+    emit(&lines.join("\n"), opts)?;
+    Ok(None)
+}
+
+/// Renders one CSV record, quoting any field that needs it.
+pub(crate) fn csv_line<S: AsRef<str>>(fields: impl IntoIterator<Item = S>) -> String {
+    fields.into_iter()
+        .map(|field| csv_field(field.as_ref()).into_owned())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::from(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::from(field)
+    }
+}
+
+pub(crate) fn get_style(table_style: TableStyle, color: bool) -> RawStyle {
+    let mut style = match table_style {
+        TableStyle::Modern => RawStyle::from(Style::modern()),
+        TableStyle::Plain => RawStyle::from(Style::blank()),
+        TableStyle::Markdown => RawStyle::from(Style::markdown()),
+        TableStyle::Ascii => RawStyle::from(Style::ascii())
+    };
+    if !color || matches!(table_style, TableStyle::Plain | TableStyle::Markdown) {
+        return style;
+    }
+
     style
         .set_color_top(Color::FG_GREEN)
         .set_color_bottom(Color::FG_GREEN)
@@ -34,4 +231,23 @@ pub(crate) fn get_style() -> RawStyle {
         .set_color_vertical(Color::FG_GREEN);
 
     return style;
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_csv_fields_that_need_it() {
+        assert_eq!(csv_line(["vm-1", "eastus"]), "vm-1,eastus");
+        assert_eq!(csv_line(["a,b", "c"]), "\"a,b\",c");
+        assert_eq!(csv_line(["say \"hi\""]), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_line(["line\nbreak", "cr\r"]), "\"line\nbreak\",\"cr\r\"");
+        assert_eq!(csv_line(["", "x", ""]), ",x,");
+    }
+
+    #[test]
+    fn serializes_durations_as_seconds() {
+        let timing = Timing { operation: "start VM".to_owned(), count: 2, total: Duration::from_millis(1500) };
+        assert_eq!(serde_json::to_value(&timing).unwrap()["total"], 1.5);
+    }
+}
@@ -0,0 +1,102 @@
+use tabled::Tabled;
+use azure_mgmt_recoveryservicesbackup::models::{ProtectedItemResource, RecoveryPointResource};
+use serde_json::Value;
+
+use std::borrow::Cow;
+use std::io;
+use std::iter;
+
+use crate::{DisplayOptions, Output, emit, get_style, print_serialized, tabulate};
+
+
+pub fn display_protected_item(out: Output<ProtectedItemResource>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(item) => tabulate(iter::once(Row(item)), opts)?,
+        Output::Multiple(items) => tabulate(items.iter().map(|item| Row(item)), opts)?
+    };
+    let Some(mut table) = table else { return Ok(()) };
+
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+struct Row<'a>(&'a ProtectedItemResource);
+
+impl<'a> Tabled for Row<'a> {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        // The properties are a union with a variant per workload type, but the
+        // fields we show are common to all of them, so read them generically.
+        let properties = self.0.properties.as_ref()
+            .and_then(|properties| serde_json::to_value(properties).ok())
+            .unwrap_or(Value::Null);
+
+        let field = |key: &str| Cow::from(
+            properties.get(key).and_then(|v| v.as_str()).unwrap_or("").to_owned()
+        );
+
+        vec![
+            field("friendlyName"),
+            field("lastBackupStatus"),
+            field("lastBackupTime"),
+            field("protectionState")
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Name"),
+            Cow::from("Last Backup Status"),
+            Cow::from("Last Backup Time"),
+            Cow::from("Protection State")
+        ]
+    }
+}
+
+/// Displays recovery points numbered from 1 so one can be picked by index.
+pub fn display_recovery_points(points: &[RecoveryPointResource], opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Multiple(points), opts);
+    }
+
+    let Some(mut table) = tabulate(points.iter().enumerate().map(|(i, point)| PointRow(i + 1, point)), opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+struct PointRow<'a>(usize, &'a RecoveryPointResource);
+
+impl<'a> Tabled for PointRow<'a> {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let properties = self.1.properties.as_ref()
+            .and_then(|properties| serde_json::to_value(properties).ok())
+            .unwrap_or(Value::Null);
+
+        let field = |key: &str| Cow::from(
+            properties.get(key).and_then(|v| v.as_str()).unwrap_or("").to_owned()
+        );
+
+        vec![
+            Cow::from(self.0.to_string()),
+            Cow::from(self.1.resource.name.as_deref().unwrap_or("")),
+            field("recoveryPointTime"),
+            field("recoveryPointType")
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("#"),
+            Cow::from("Recovery Point"),
+            Cow::from("Time"),
+            Cow::from("Type")
+        ]
+    }
+}
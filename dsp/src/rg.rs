@@ -1,20 +1,76 @@
-use tabled::{Table, Tabled};
+use tabled::Tabled;
 use azure_mgmt_resources::models::{ResourceGroup, ResourceGroupProperties};
+use serde::Serialize;
 
 use std::borrow::Cow;
+use std::io;
 use std::iter;
 
-use crate::{Output, get_style};
+use crate::{DisplayOptions, Output, emit, get_style, print_serialized, tabulate};
 
 
-pub fn display_rg(out: Output<ResourceGroup>) {
-    let mut table = match out {
-        Output::Single(group) => Table::new(iter::once(Row(group))),
-        Output::Multiple(groups) => Table::new(groups.iter().map(|group| Row(group)))
+pub fn display_rg(out: Output<ResourceGroup>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(group) => tabulate(iter::once(Row(group)), opts)?,
+        Output::Multiple(groups) => tabulate(groups.iter().map(|group| Row(group)), opts)?
     };
+    let Some(mut table) = table else { return Ok(()) };
+
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
 
-    table.with(get_style());
-    println!("{table}");
+/// A resource group along with the subscription it was listed from.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionGroup {
+    pub subscription: String,
+    #[serde(flatten)]
+    pub group: ResourceGroup
+}
+
+/// Displays resource groups from several subscriptions with a column naming
+/// the subscription of each.
+pub fn display_rg_by_sub(out: Output<SubscriptionGroup>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(group) => tabulate(iter::once(SubRow(group)), opts)?,
+        Output::Multiple(groups) => tabulate(groups.iter().map(|group| SubRow(group)), opts)?
+    };
+    let Some(mut table) = table else { return Ok(()) };
+
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// How many VMs a resource group holds and how many of them are running.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct VmCounts {
+    pub total: usize,
+    pub running: usize
+}
+
+/// Displays a single resource group along with counts of its VMs.
+pub fn display_rg_with_vms(group: &ResourceGroup, vms: &VmCounts, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        #[derive(Serialize)]
+        struct WithVms<'a> {
+            #[serde(flatten)]
+            group: &'a ResourceGroup,
+            vms: &'a VmCounts
+        }
+        return print_serialized(&Output::Single(&WithVms { group, vms }), opts);
+    }
+
+    let Some(mut table) = tabulate(iter::once(CountsRow(group, vms)), opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
 }
 
 struct Row<'a>(&'a ResourceGroup);
@@ -23,11 +79,7 @@ impl<'a> Tabled for Row<'a> {
     const LENGTH: usize = 3;
 
     fn fields(&self) -> Vec<Cow<'_, str>> {
-        vec![ 
-            Cow::from(self.0.name.as_deref().unwrap_or("")), 
-            Cow::from(&self.0.location),
-            Cow::from(Self::get_state(&self.0.properties))   
-        ]
+        Self::group_fields(self.0)
     }
 
     fn headers() -> Vec<Cow<'static, str>> {
@@ -40,6 +92,14 @@ impl<'a> Tabled for Row<'a> {
 }
 
 impl<'a> Row<'a> {
+    fn group_fields(group: &ResourceGroup) -> Vec<Cow<'_, str>> {
+        vec![ 
+            Cow::from(group.name.as_deref().unwrap_or("")), 
+            Cow::from(&group.location),
+            Cow::from(Self::get_state(&group.properties))   
+        ]
+    }
+
     fn get_state(state: &Option<ResourceGroupProperties>) -> &str {
         match state {
             Some(properties) => properties.provisioning_state.as_deref().unwrap_or(""),
@@ -47,3 +107,41 @@ impl<'a> Row<'a> {
         }
     }
 }
+
+struct CountsRow<'a>(&'a ResourceGroup, &'a VmCounts);
+
+impl<'a> Tabled for CountsRow<'a> {
+    const LENGTH: usize = 5;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let mut fields = Row::group_fields(self.0);
+        fields.push(Cow::from(self.1.total.to_string()));
+        fields.push(Cow::from(self.1.running.to_string()));
+        fields
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        let mut headers = Row::headers();
+        headers.push(Cow::from("VM Count"));
+        headers.push(Cow::from("Running"));
+        headers
+    }
+}
+
+struct SubRow<'a>(&'a SubscriptionGroup);
+
+impl<'a> Tabled for SubRow<'a> {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let mut fields = vec![Cow::from(self.0.subscription.as_str())];
+        fields.extend(Row::group_fields(&self.0.group));
+        fields
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        let mut headers = vec![Cow::from("Subscription")];
+        headers.extend(Row::headers());
+        headers
+    }
+}
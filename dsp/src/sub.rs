@@ -1,39 +1,77 @@
-use tabled::{Table, Tabled};
+use tabled::Tabled;
 use azure_mgmt_subscription::{
     models::subscription::State, 
     models::Subscription
 };
+use serde::Serialize;
 use std::borrow::Cow;
+use std::io;
 use std::iter;
 
-use crate::{Output, get_style};
+use crate::{DisplayOptions, Output, emit, get_style, print_serialized, tabulate};
 
 
-pub fn display_sub(out: Output<Subscription>) {
-    let mut table = match out {
-        Output::Single(sub) => Table::new(iter::once(Row(sub))),
-        Output::Multiple(subs) => Table::new(subs.iter().map(|sub| Row(sub)))
+/// Displays subscriptions, marking the one whose ID matches `current` with a `*`.
+pub fn display_sub(out: Output<Subscription>, current: Option<&str>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let is_current = |sub: &Subscription| current.is_some_and(|id| {
+        sub.subscription_id.as_deref().is_some_and(|sub_id| sub_id.eq_ignore_ascii_case(id))
+    });
+
+    // The policies are only worth the width when looking at one subscription.
+    let table = match out {
+        Output::Single(sub) => tabulate(iter::once(DetailRow(sub, is_current(sub))), opts)?,
+        Output::Multiple(subs) => tabulate(subs.iter().map(|sub| Row(sub, is_current(sub))), opts)?
     };
+    let Some(mut table) = table else { return Ok(()) };
+
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
 
-    table.with(get_style());
-    println!("{table}");
+/// The cost a subscription has run up since the start of the month.
+#[derive(Debug, Clone, Serialize)]
+pub struct Spending {
+    pub month_to_date: f64,
+    pub currency: String
 }
 
-struct Row<'a>(&'a Subscription);
+/// Displays a single subscription along with its month-to-date cost.
+pub fn display_sub_with_spending(sub: &Subscription, current: Option<&str>, spending: &Spending, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        #[derive(Serialize)]
+        struct WithSpending<'a> {
+            #[serde(flatten)]
+            sub: &'a Subscription,
+            spending: &'a Spending
+        }
+        return print_serialized(&Output::Single(&WithSpending { sub, spending }), opts);
+    }
+
+    let is_current = current.is_some_and(|id| {
+        sub.subscription_id.as_deref().is_some_and(|sub_id| sub_id.eq_ignore_ascii_case(id))
+    });
+
+    let Some(mut table) = tabulate(iter::once(SpendingRow(DetailRow(sub, is_current), spending)), opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+struct Row<'a>(&'a Subscription, bool);
 
 impl<'a> Tabled for Row<'a> {
-    const LENGTH: usize = 3;
+    const LENGTH: usize = 4;
 
     fn fields(&self) -> Vec<Cow<'_, str>> {
-        vec![ 
-            Cow::from(self.0.subscription_id.as_deref().unwrap_or("")), 
-            Cow::from(self.0.display_name.as_deref().unwrap_or("")),
-            Cow::from(Self::get_state(&self.0.state))   
-        ]
+        Self::sub_fields(self.0, self.1)
     }
 
     fn headers() -> Vec<Cow<'static, str>> {
         vec![
+            Cow::from(""),
             Cow::from("Subscription ID"), 
             Cow::from("Name"),
             Cow::from("State")
@@ -42,6 +80,15 @@ impl<'a> Tabled for Row<'a> {
 }
 
 impl<'a> Row<'a> {
+    fn sub_fields(sub: &Subscription, current: bool) -> Vec<Cow<'_, str>> {
+        vec![ 
+            Cow::from(if current { "*" } else { "" }),
+            Cow::from(sub.subscription_id.as_deref().unwrap_or("")), 
+            Cow::from(sub.display_name.as_deref().unwrap_or("")),
+            Cow::from(Self::get_state(&sub.state))   
+        ]
+    }
+
     fn get_state(state: &Option<State>) -> &'static str {
         match state {
             Some(State::Enabled) => "Enabled",
@@ -53,3 +100,51 @@ impl<'a> Row<'a> {
         }
     }
 }
+
+struct DetailRow<'a>(&'a Subscription, bool);
+
+impl<'a> Tabled for DetailRow<'a> {
+    const LENGTH: usize = 6;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let policies = self.0.subscription_policies.as_ref();
+
+        // SpendingLimit is a generated enum, so take its wire name rather
+        // than duplicating the variants here.
+        let spending_limit = policies
+            .and_then(|policies| policies.spending_limit.as_ref())
+            .and_then(|limit| serde_json::to_value(limit).ok())
+            .and_then(|limit| limit.as_str().map(|limit| limit.to_owned()))
+            .unwrap_or_default();
+
+        let mut fields = Row::sub_fields(self.0, self.1);
+        fields.push(Cow::from(policies.and_then(|policies| policies.quota_id.as_deref()).unwrap_or("")));
+        fields.push(Cow::from(spending_limit));
+        fields
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        let mut headers = Row::headers();
+        headers.push(Cow::from("Quota ID"));
+        headers.push(Cow::from("Spending Limit"));
+        headers
+    }
+}
+
+struct SpendingRow<'a>(DetailRow<'a>, &'a Spending);
+
+impl<'a> Tabled for SpendingRow<'a> {
+    const LENGTH: usize = 7;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let mut fields = self.0.fields();
+        fields.push(Cow::from(format!("{:.2} {}", self.1.month_to_date, self.1.currency)));
+        fields
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        let mut headers = DetailRow::headers();
+        headers.push(Cow::from("Cost (MTD)"));
+        headers
+    }
+}
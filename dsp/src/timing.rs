@@ -0,0 +1,50 @@
+use tabled::{Table, Tabled};
+use serde::Serialize;
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use crate::{TableStyle, get_style};
+
+/// How often an Azure operation ran and how long it took in total.
+#[derive(Debug, Clone, Serialize)]
+pub struct Timing {
+    pub operation: String,
+    pub count: u32,
+
+    /// Serialized as seconds.
+    #[serde(serialize_with = "crate::serialize_secs")]
+    pub total: Duration
+}
+
+/// Prints timings to stderr, so they never mix with the command's output.
+pub fn display_timings(timings: &[Timing], style: TableStyle, color: bool) {
+    let mut table = Table::new(timings.iter().map(Row));
+    table.with(get_style(style, color));
+    eprintln!("{table}");
+}
+
+struct Row<'a>(&'a Timing);
+
+impl<'a> Tabled for Row<'a> {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let average = self.0.total / self.0.count.max(1);
+        vec![
+            Cow::from(self.0.operation.as_str()),
+            Cow::from(self.0.count.to_string()),
+            Cow::from(format!("{:.2?}", self.0.total)),
+            Cow::from(format!("{:.2?}", average))
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Operation"),
+            Cow::from("Count"),
+            Cow::from("Total"),
+            Cow::from("Average")
+        ]
+    }
+}
@@ -0,0 +1,55 @@
+use tabled::Tabled;
+use azure_mgmt_recoveryservices::models::Vault;
+use serde_json::Value;
+
+use std::borrow::Cow;
+use std::io;
+use std::iter;
+
+use crate::{DisplayOptions, Output, emit, get_style, print_serialized, tabulate};
+use crate::vm::resource_group;
+
+
+pub fn display_vaults(out: Output<Vault>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(vault) => tabulate(iter::once(Row(vault)), opts)?,
+        Output::Multiple(vaults) => tabulate(vaults.iter().map(|vault| Row(vault)), opts)?
+    };
+    let Some(mut table) = table else { return Ok(()) };
+
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+struct Row<'a>(&'a Vault);
+
+impl<'a> Tabled for Row<'a> {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        // Read the fields from JSON as the model nests them several levels
+        // deep and the SKU name is an enum.
+        let vault = serde_json::to_value(self.0).unwrap_or(Value::Null);
+        let field = |value: &Value| Cow::from(value.as_str().unwrap_or("").to_owned());
+
+        vec![
+            field(&vault["name"]),
+            Cow::from(resource_group(vault["id"].as_str()).to_owned()),
+            field(&vault["location"]),
+            field(&vault["sku"]["name"])
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Name"),
+            Cow::from("Resource Group"),
+            Cow::from("Location"),
+            Cow::from("SKU")
+        ]
+    }
+}
@@ -1,11 +1,14 @@
 use tabled::{
     Table,
     Tabled,
+    builder::Builder,
     settings::{
         object::{Columns, Rows, Object},
+        peaker::PriorityMax,
         Color,
         Modify,
-        CellOption
+        CellOption,
+        Width
     },
     grid::{
         config::{ColoredConfig, Entity},
@@ -13,28 +16,434 @@ use tabled::{
     }
 };
 
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
 use std::borrow::Cow;
+use std::io::{self, IsTerminal};
 use std::iter;
-use azure_mgmt_compute::models::VirtualMachine;
-use crate::{Output, get_style};
+use std::time::Duration;
+use azure_mgmt_compute::models::{ImageReference, InstanceViewStatus, RetrieveBootDiagnosticsDataResult, StorageProfile, VirtualMachine, VirtualMachineInstanceView};
+use crate::{DisplayOptions, Format, Output, csv_line, emit, get_style, print_serialized, tabulate};
 
-pub fn display_vm(out: Output<VirtualMachine>) {
-    let mut table = match out {
-        Output::Single(vm) => Table::new(iter::once(Row(vm))),
-        Output::Multiple(vms) => Table::new(vms.iter().map(|vm| Row(vm)))
+/// The IP addresses assigned to a VM's network interfaces.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct NetworkAddresses {
+    pub private: Vec<String>,
+    pub public: Vec<String>
+}
+
+/// The network security group guarding a NIC, and its inbound allow rules.
+#[derive(Debug, Clone, Serialize)]
+pub struct NicSecurity {
+    pub nic: String,
+    /// `None` when neither the NIC nor its subnet has an NSG.
+    pub nsg: Option<String>,
+    /// Whether the NSG is inherited from the NIC's subnet.
+    pub from_subnet: bool,
+    pub rules: Vec<NsgRule>
+}
+
+/// A security rule, flattened to the fields that matter for a port review.
+#[derive(Debug, Clone, Serialize)]
+pub struct NsgRule {
+    pub name: String,
+    pub priority: Option<i32>,
+    pub protocol: String,
+    pub ports: String,
+    pub source: String,
+    pub action: String
+}
+
+/// A disk attached to a VM and how it is encrypted at rest.
+#[derive(Debug, Clone, Serialize)]
+pub struct VmDisk {
+    pub name: String,
+    /// `None` for the OS disk.
+    pub lun: Option<i32>,
+    pub size_gb: Option<i32>,
+    pub sku: Option<String>,
+    /// `None` for unmanaged disks, which are VHD blobs rather than disk
+    /// resources and don't report their encryption.
+    pub encryption: Option<DiskEncryption>,
+    /// Whether the VM encrypts temp disks and caches on its host.
+    pub encryption_at_host: bool
+}
+
+/// The keys a managed disk is encrypted with.
+#[derive(Debug, Clone, Serialize)]
+pub enum DiskEncryption {
+    PlatformKey,
+    CustomerKey,
+    /// Double encryption, with both platform and customer-managed keys.
+    PlatformAndCustomerKeys,
+    /// A type this version doesn't know about, as Azure reported it.
+    Other(String)
+}
+
+/// A column of the VM table, selectable with `DisplayOptions::columns`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VmColumn {
+    Name,
+    Location,
+    Os,
+    Sku,
+    Version,
+    PrivateIp,
+    PublicIp,
+    Tags,
+    Status,
+    Disks,
+    TotalGb,
+    ResourceGroup,
+    /// Whether an update of the VM's model is in progress, separate from its power state.
+    ProvisioningState,
+    Zone
+}
+
+impl VmColumn {
+    /// The columns shown when none are selected.
+    pub const DEFAULT: [VmColumn; 6] = [
+        VmColumn::Name,
+        VmColumn::Location,
+        VmColumn::Os,
+        VmColumn::Sku,
+        VmColumn::Version,
+        VmColumn::Status
+    ];
+
+    /// Every column, shown when none are selected and `--wide` is passed.
+    /// The resource group is left out unless the VMs span several groups,
+    /// and the IPs unless the VM's addresses were fetched.
+    pub const ALL: [VmColumn; 14] = [
+        VmColumn::Name,
+        VmColumn::Location,
+        VmColumn::Os,
+        VmColumn::Sku,
+        VmColumn::Version,
+        VmColumn::PrivateIp,
+        VmColumn::PublicIp,
+        VmColumn::Tags,
+        VmColumn::Status,
+        VmColumn::Disks,
+        VmColumn::TotalGb,
+        VmColumn::ResourceGroup,
+        VmColumn::ProvisioningState,
+        VmColumn::Zone
+    ];
+}
+
+pub fn display_vm(out: Output<VirtualMachine>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    match out {
+        Output::Single(vm) => print_vm_table(iter::once(Row(vm, None)), false, false, opts),
+        Output::Multiple(vms) => print_vm_table(vms.iter().map(|vm| Row(vm, None)), false, false, opts)
+    }
+}
+
+/// Displays VMs from across a subscription, adding a resource group column
+/// to the default columns.
+pub fn display_vm_all_groups(vms: &[VirtualMachine], opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Multiple(vms), opts);
+    }
+
+    print_vm_table(vms.iter().map(|vm| Row(vm, None)), true, false, opts)?;
+    if opts.format == Format::Table && !vms.is_empty() {
+        emit(&fleet_summary(vms), opts)?;
+    }
+    Ok(())
+}
+
+/// Summarises VMs as e.g. `42 VMs: 30 running, 10 deallocated, 2 unknown
+/// across 7 resource groups`, most common state first.
+fn fleet_summary(vms: &[VirtualMachine]) -> String {
+    let mut states: Vec<(String, usize)> = Vec::new();
+    for vm in vms {
+        let state = vm.properties.as_ref()
+            .and_then(|properties| properties.instance_view.as_ref())
+            .map(|view| power_state(view))
+            .map(|state| state.strip_prefix("VM ").unwrap_or(&state).to_lowercase())
+            .unwrap_or_else(|| "unknown".to_owned());
+
+        match states.iter_mut().find(|(name, _)| *name == state) {
+            Some((_, count)) => *count += 1,
+            None => states.push((state, 1))
+        }
+    }
+    states.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+
+    let mut groups: Vec<String> = vms.iter()
+        .map(|vm| resource_group(vm.resource.id.as_deref()).to_lowercase())
+        .collect();
+    groups.sort();
+    groups.dedup();
+
+    let states: Vec<String> = states.iter().map(|(state, count)| format!("{count} {state}")).collect();
+    let plural = |count: usize, word: &str| if count == 1 { format!("{count} {word}") } else { format!("{count} {word}s") };
+    format!(
+        "{}: {} across {}",
+        plural(vms.len(), "VM"),
+        states.join(", "),
+        plural(groups.len(), "resource group")
+    )
+}
+
+/// Displays a single VM along with the addresses of its network interfaces.
+pub fn display_vm_with_addresses(vm: &VirtualMachine, addresses: &NetworkAddresses, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        #[derive(Serialize)]
+        struct WithAddresses<'a> {
+            #[serde(flatten)]
+            vm: &'a VirtualMachine,
+            addresses: &'a NetworkAddresses
+        }
+        return print_serialized(&Output::Single(&WithAddresses { vm, addresses }), opts);
+    }
+
+    print_vm_table(iter::once(Row(vm, Some(addresses))), false, true, opts)
+}
+
+/// Displays the SAS URLs of a VM's boot diagnostics.
+pub fn display_boot_diagnostics(data: &RetrieveBootDiagnosticsDataResult, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Single(data), opts);
+    }
+
+    let rows = [
+        LinkRow("Console Screenshot", data.console_screenshot_blob_uri.as_deref()),
+        LinkRow("Serial Log", data.serial_console_log_blob_uri.as_deref())
+    ];
+
+    let Some(mut table) = tabulate(rows, opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// The power state of a single VM.
+#[derive(Debug, Clone, Serialize)]
+pub struct VmStatus {
+    pub name: String,
+    pub status: String
+}
+
+pub fn display_vm_status(out: Output<VmStatus>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(status) => tabulate(iter::once(StatusRow(status)), opts)?,
+        Output::Multiple(statuses) => tabulate(statuses.iter().map(|status| StatusRow(status)), opts)?
+    };
+    let Some(table) = table else { return Ok(()) };
+
+    print_table(table, opts)
+}
+
+/// Displays every status of a VM's instance view, including those reported
+/// by the VM agent and each extension.
+pub fn display_instance_view(view: &VirtualMachineInstanceView, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Single(view), opts);
+    }
+
+    let vm = view.statuses.iter().map(|status| InstanceStatusRow(Cow::from("VM"), status));
+    let agent = view.vm_agent.iter().flat_map(|agent| {
+        let source = match agent.vm_agent_version.as_deref() {
+            Some(version) => Cow::from(format!("Agent {version}")),
+            None => Cow::from("Agent")
+        };
+        agent.statuses.iter().map(move |status| InstanceStatusRow(source.clone(), status))
+    });
+    let extensions = view.extensions.iter().flat_map(|extension| {
+        let source = Cow::from(format!("Extension {}", extension.name.as_deref().unwrap_or("?")));
+        extension.statuses.iter()
+            .chain(extension.substatuses.iter())
+            .map(move |status| InstanceStatusRow(source.clone(), status))
+    });
+
+    let Some(mut table) = tabulate(vm.chain(agent).chain(extensions), opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// The outcome of a start, stop or restart of one VM.
+#[derive(Debug, Clone, Serialize)]
+pub struct VmOperationResult {
+    pub name: String,
+    pub group: String,
+    pub succeeded: bool,
+
+    /// The VM was already in the target state, so no request was sent.
+    pub skipped: bool,
+
+    /// The power state reached, or why it wasn't.
+    pub final_state: String,
+
+    /// How long the VM took, serialized as seconds.
+    #[serde(serialize_with = "crate::serialize_secs")]
+    pub elapsed: Duration
+}
+
+pub fn display_vm_results(out: Output<VmOperationResult>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(result) => tabulate(iter::once(ResultRow(result)), opts)?,
+        Output::Multiple(results) => tabulate(results.iter().map(|result| ResultRow(result)), opts)?
     };
+    let Some(table) = table else { return Ok(()) };
 
-    table
-        .with(get_style())
-        .with(Modify::new(Columns::last().not(Rows::first())).with(Colorization));
+    print_table(table, opts)
+}
 
-    println!("{table}");
+/// A short name for a marketplace image.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageAlias {
+    pub alias: String,
+    pub image: ImageReference
 }
 
-struct Row<'a>(&'a VirtualMachine);
+pub fn display_image_aliases(out: Output<ImageAlias>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(alias) => tabulate(iter::once(ImageRow(alias)), opts)?,
+        Output::Multiple(aliases) => tabulate(aliases.iter().map(|alias| ImageRow(alias)), opts)?
+    };
+    let Some(mut table) = table else { return Ok(()) };
+
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// An operation that would be applied to a VM.
+#[derive(Debug, Clone, Serialize)]
+pub struct VmAction {
+    pub name: String,
+    pub action: String
+}
+
+pub fn display_vm_actions(out: Output<VmAction>, opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&out, opts);
+    }
+
+    let table = match out {
+        Output::Single(action) => tabulate(iter::once(ActionRow(action)), opts)?,
+        Output::Multiple(actions) => tabulate(actions.iter().map(|action| ActionRow(action)), opts)?
+    };
+    let Some(mut table) = table else { return Ok(()) };
+
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// Reads the power state from an instance view.
+///
+/// Prefers the `display_status` of the `PowerState/*` status, falling back to
+/// one derived from the code (e.g. `PowerState/running` -> `VM running`) since
+/// Azure occasionally omits the display status on transitional states.
+pub fn power_state(view: &VirtualMachineInstanceView) -> Cow<'_, str> {
+    let status = view.statuses.iter().find_map(|s| {
+        let code = s.code.as_deref()?;
+        let (prefix, state) = code.split_once('/')?;
+        prefix.eq_ignore_ascii_case("PowerState").then_some((s, state))
+    });
+
+    match status {
+        Some((s, _)) if s.display_status.as_deref().is_some_and(|d| !d.is_empty()) => {
+            Cow::from(s.display_status.as_deref().unwrap_or_default())
+        },
+        Some((_, state)) => Cow::from(format!("VM {}", state.to_lowercase())),
+        None => Cow::from("Unknown")
+    }
+}
+
+fn print_table(mut table: Table, opts: &DisplayOptions) -> io::Result<()> {
+    table.with(get_style(opts.style, opts.color));
+    if opts.color_cells() {
+        table.with(Modify::new(Columns::last().not(Rows::first())).with(Colorization));
+    }
+
+    emit(&table, opts)
+}
+
+/// Prints the selected columns of `rows`, coloring the status column if shown.
+/// Unless columns were selected, `all_groups` shows the resource group next
+/// to the name and `addresses` shows the IPs after the image version, since
+/// only rows built with their NICs' addresses have any to show.
+fn print_vm_table<'a>(rows: impl Iterator<Item = Row<'a>>, all_groups: bool, addresses: bool, opts: &DisplayOptions) -> io::Result<()> {
+    let columns = match opts.columns.as_slice() {
+        [] => {
+            let defaults = if opts.wide { &VmColumn::ALL[..] } else { &VmColumn::DEFAULT[..] };
+            let mut columns: Vec<VmColumn> = defaults.iter()
+                .copied()
+                .filter(|column| !matches!(column, VmColumn::ResourceGroup | VmColumn::PrivateIp | VmColumn::PublicIp))
+                .collect();
+
+            if all_groups {
+                columns.insert(1, VmColumn::ResourceGroup);
+            }
+            if addresses {
+                let at = columns.iter().position(|column| *column == VmColumn::Version).map_or(columns.len(), |i| i + 1);
+                columns.splice(at..at, [VmColumn::PrivateIp, VmColumn::PublicIp]);
+            }
+            columns
+        },
+        columns => columns.to_vec()
+    };
+
+    let all_headers = Row::headers();
+    let headers = columns.iter().map(|column| all_headers[*column as usize].clone());
+    if opts.format == Format::Csv {
+        let mut lines = vec![csv_line(headers)];
+        lines.extend(rows.map(|row| {
+            let fields = row.fields();
+            csv_line(columns.iter().map(|column| fields[*column as usize].clone()))
+        }));
+        return emit(&lines.join("\n"), opts);
+    }
+
+    let mut builder = Builder::default();
+    builder.set_header(headers);
+
+    for row in rows {
+        let fields = row.fields();
+        builder.push_record(columns.iter().map(|column| fields[*column as usize].clone()));
+    }
+
+    let mut table = builder.build();
+    table.with(get_style(opts.style, opts.color));
+
+    // Long image names easily overflow narrow terminals, so shrink the widest
+    // columns to fit unless the output is going somewhere else.
+    if !opts.wide && opts.output_file.is_none() && io::stdout().is_terminal() {
+        if let Ok((width, _)) = crossterm::terminal::size() {
+            table.with(Width::truncate(width as usize).suffix("…").priority::<PriorityMax>());
+        }
+    }
+
+    let status = columns.iter().position(|column| *column == VmColumn::Status);
+    if let (true, Some(column)) = (opts.color_cells(), status) {
+        table.with(Modify::new(Columns::single(column).not(Rows::first())).with(Colorization));
+    }
+
+    emit(&table, opts)
+}
+
+struct Row<'a>(&'a VirtualMachine, Option<&'a NetworkAddresses>);
 
 impl<'a> Tabled for Row<'a> {
-    const LENGTH: usize = 6;
+    const LENGTH: usize = 14;
 
     fn fields(&self) -> Vec<Cow<'_, str>> {
         let mut vec = vec![
@@ -42,32 +451,42 @@ impl<'a> Tabled for Row<'a> {
             Cow::from(self.0.resource.location.as_str())
         ];
 
-        if let Some(ref properties) = self.0.properties {
-            let os_info = properties.storage_profile.as_ref()
-                .and_then(|profile| profile.image_reference.as_ref().and_then(|image| {
-                    Some((
-                        image.offer.as_deref().unwrap_or(""),
-                        image.sku.as_deref().unwrap_or(""),
-                        image.version.as_deref().unwrap_or("")
-                    ))
-                }));
-
-            vec.push(Cow::from(os_info.and_then(|(os, _, _)| Some(os)).unwrap_or("")));
-            vec.push(Cow::from(os_info.and_then(|(_, sku, _)| Some(sku)).unwrap_or("")));
-            vec.push(Cow::from(os_info.and_then(|(_, _, version)| Some(version)).unwrap_or("")));
-
-            let status = match &properties.instance_view {
-                Some(view) => {
-                    view.statuses.iter()
-                        .filter(|s| s.code.as_deref().is_some_and(|c| c.contains("PowerState")))
-                        .map(|s| s.display_status.as_deref().unwrap_or_else(|| "Unknown"))
-                        .nth(0)
-                        .unwrap_or_else(|| "Unknown")
-                },
-                None => "Unknown"
-            };
-            vec.push(Cow::from(status));
-        }
+        let properties = self.0.properties.as_ref();
+        let os_info = properties
+            .and_then(|properties| properties.storage_profile.as_ref())
+            .and_then(|profile| profile.image_reference.as_ref().and_then(|image| {
+                Some((
+                    image.offer.as_deref().unwrap_or(""),
+                    image.sku.as_deref().unwrap_or(""),
+                    image.version.as_deref().unwrap_or("")
+                ))
+            }));
+
+        vec.push(Cow::from(os_info.and_then(|(os, _, _)| Some(os)).unwrap_or("")));
+        vec.push(Cow::from(os_info.and_then(|(_, sku, _)| Some(sku)).unwrap_or("")));
+        vec.push(Cow::from(os_info.and_then(|(_, _, version)| Some(version)).unwrap_or("")));
+
+        let (private, public) = match self.1 {
+            Some(addresses) => (addresses.private.join(", "), addresses.public.join(", ")),
+            None => (String::new(), String::new())
+        };
+        vec.push(Cow::from(private));
+        vec.push(Cow::from(public));
+        vec.push(Cow::from(format_tags(self.0.resource.tags.as_ref())));
+
+        let status = match properties.and_then(|properties| properties.instance_view.as_ref()) {
+            Some(view) => power_state(view),
+            None => Cow::from("Unknown")
+        };
+        vec.push(status);
+
+        let storage = properties.and_then(|properties| properties.storage_profile.as_ref());
+        let (disks, total) = disk_usage(storage);
+        vec.push(Cow::from(disks.to_string()));
+        vec.push(total.map_or(Cow::from("?"), |total| Cow::from(total.to_string())));
+        vec.push(Cow::from(resource_group(self.0.resource.id.as_deref())));
+        vec.push(Cow::from(properties.and_then(|properties| properties.provisioning_state.as_deref()).unwrap_or("")));
+        vec.push(Cow::from(self.0.zones.join(", ")));
 
         vec
     }
@@ -79,11 +498,335 @@ impl<'a> Tabled for Row<'a> {
             Cow::from("OS"),
             Cow::from("SKU"),
             Cow::from("Version"),
+            Cow::from("Private IP"),
+            Cow::from("Public IP"),
+            Cow::from("Tags"),
+            Cow::from("Status"),
+            Cow::from("Disks"),
+            Cow::from("Total GB"),
+            Cow::from("Resource Group"),
+            Cow::from("Provisioning State"),
+            Cow::from("Zone")
+        ]
+    }
+}
+
+/// Reads the resource group out of an ID like `/subscriptions/{sub}/resourceGroups/{group}/...`.
+pub(crate) fn resource_group(id: Option<&str>) -> &str {
+    let mut segments = id.unwrap_or_default().split('/');
+    segments.find(|segment| segment.eq_ignore_ascii_case("resourceGroups"));
+    segments.next().unwrap_or_default()
+}
+
+/// Counts a VM's data disks and sums the size of every disk, OS disk
+/// included. The total is `None` when any size hasn't been populated.
+fn disk_usage(storage: Option<&StorageProfile>) -> (usize, Option<i64>) {
+    let Some(storage) = storage else {
+        return (0, None);
+    };
+
+    let os_disk = storage.os_disk.as_ref().map(|disk| disk.disk_size_gb);
+    let total = os_disk.into_iter()
+        .chain(storage.data_disks.iter().map(|disk| disk.disk_size_gb))
+        .map(|size| size.map(i64::from))
+        .sum::<Option<i64>>();
+
+    (storage.data_disks.len(), total)
+}
+
+/// Displays a resource's tags, one per row sorted by key.
+pub fn display_tags(tags: Option<&Value>, opts: &DisplayOptions) -> io::Result<()> {
+    let empty = Value::Object(Default::default());
+    let tags = tags.unwrap_or(&empty);
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Single(tags), opts);
+    }
+
+    let mut pairs: Vec<(&String, &Value)> = tags.as_object()
+        .map(|tags| tags.iter().collect())
+        .unwrap_or_default();
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let Some(mut table) = tabulate(pairs.into_iter().map(|(key, value)| TagRow(key, value)), opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// Displays the inbound rules of the NSG guarding each NIC, one row per rule.
+pub fn display_nsg_rules(nics: &[NicSecurity], opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Multiple(nics), opts);
+    }
+
+    let rows = nics.iter()
+        .flat_map(|nic| nic.rules.iter().map(move |rule| NsgRow(nic, rule)));
+
+    let Some(mut table) = tabulate(rows, opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// Displays the disks of a VM with their encryption, OS disk first.
+pub fn display_disks(disks: &[VmDisk], opts: &DisplayOptions) -> io::Result<()> {
+    if opts.format.is_serialized() {
+        return print_serialized(&Output::Multiple(disks), opts);
+    }
+
+    let Some(mut table) = tabulate(disks.iter().map(DiskRow), opts)? else { return Ok(()) };
+    table.with(get_style(opts.style, opts.color));
+    emit(&table, opts)
+}
+
+/// Renders tags as `key=value` pairs sorted by key.
+fn format_tags(tags: Option<&Value>) -> String {
+    let mut pairs: Vec<(&String, &Value)> = tags
+        .and_then(|tags| tags.as_object())
+        .map(|tags| tags.iter().collect())
+        .unwrap_or_default();
+
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    pairs.iter()
+        .map(|(key, value)| format!("{key}={}", value.as_str().unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+struct TagRow<'a>(&'a str, &'a Value);
+
+impl<'a> Tabled for TagRow<'a> {
+    const LENGTH: usize = 2;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        vec![
+            Cow::from(self.0),
+            Cow::from(self.1.as_str().unwrap_or_default())
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Key"),
+            Cow::from("Value")
+        ]
+    }
+}
+
+struct DiskRow<'a>(&'a VmDisk);
+
+impl<'a> Tabled for DiskRow<'a> {
+    const LENGTH: usize = 6;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let disk = self.0;
+        let encryption = match &disk.encryption {
+            Some(DiskEncryption::PlatformKey) => Cow::from("Platform-managed key"),
+            Some(DiskEncryption::CustomerKey) => Cow::from("Customer-managed key"),
+            Some(DiskEncryption::PlatformAndCustomerKeys) => Cow::from("Double (platform and customer keys)"),
+            Some(DiskEncryption::Other(kind)) => Cow::from(kind.as_str()),
+            None => Cow::from("Unmanaged disk")
+        };
+
+        vec![
+            Cow::from(disk.name.as_str()),
+            Cow::from(disk.lun.map(|lun| lun.to_string()).unwrap_or_else(|| "OS".to_owned())),
+            Cow::from(disk.size_gb.map(|size| size.to_string()).unwrap_or_default()),
+            Cow::from(disk.sku.as_deref().unwrap_or_default()),
+            encryption,
+            Cow::from(if disk.encryption_at_host { "Yes" } else { "No" })
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Disk"),
+            Cow::from("LUN"),
+            Cow::from("Size (GB)"),
+            Cow::from("SKU"),
+            Cow::from("Encryption"),
+            Cow::from("Encryption at Host")
+        ]
+    }
+}
+
+struct NsgRow<'a>(&'a NicSecurity, &'a NsgRule);
+
+impl<'a> Tabled for NsgRow<'a> {
+    const LENGTH: usize = 8;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let NsgRow(nic, rule) = self;
+        let nsg = match (nic.nsg.as_deref(), nic.from_subnet) {
+            (Some(nsg), true) => Cow::from(format!("{nsg} (subnet)")),
+            (Some(nsg), false) => Cow::from(nsg),
+            (None, _) => Cow::from("")
+        };
+
+        vec![
+            Cow::from(nic.nic.as_str()),
+            nsg,
+            Cow::from(rule.priority.map(|priority| priority.to_string()).unwrap_or_default()),
+            Cow::from(rule.name.as_str()),
+            Cow::from(rule.protocol.as_str()),
+            Cow::from(rule.ports.as_str()),
+            Cow::from(rule.source.as_str()),
+            Cow::from(rule.action.as_str())
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("NIC"),
+            Cow::from("NSG"),
+            Cow::from("Priority"),
+            Cow::from("Rule"),
+            Cow::from("Protocol"),
+            Cow::from("Ports"),
+            Cow::from("Source"),
+            Cow::from("Action")
+        ]
+    }
+}
+
+struct LinkRow<'a>(&'static str, Option<&'a str>);
+
+impl<'a> Tabled for LinkRow<'a> {
+    const LENGTH: usize = 2;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        vec![
+            Cow::from(self.0),
+            Cow::from(self.1.unwrap_or("<not available>"))
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Log"),
+            Cow::from("URL")
+        ]
+    }
+}
+
+struct StatusRow<'a>(&'a VmStatus);
+
+impl<'a> Tabled for StatusRow<'a> {
+    const LENGTH: usize = 2;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        vec![
+            Cow::from(self.0.name.as_str()),
+            Cow::from(self.0.status.as_str())
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Name"),
             Cow::from("Status")
         ]
     }
 }
 
+struct InstanceStatusRow<'a>(Cow<'a, str>, &'a InstanceViewStatus);
+
+impl<'a> Tabled for InstanceStatusRow<'a> {
+    const LENGTH: usize = 6;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let status = self.1;
+        vec![
+            self.0.clone(),
+            Cow::from(status.code.as_deref().unwrap_or("")),
+            Cow::from(status.level.as_ref().map(|level| format!("{level:?}")).unwrap_or_default()),
+            Cow::from(status.display_status.as_deref().unwrap_or("")),
+            Cow::from(status.time.map(|time| time.to_string()).unwrap_or_default()),
+            Cow::from(status.message.as_deref().unwrap_or(""))
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Source"),
+            Cow::from("Code"),
+            Cow::from("Level"),
+            Cow::from("Display Status"),
+            Cow::from("Time"),
+            Cow::from("Message")
+        ]
+    }
+}
+
+struct ResultRow<'a>(&'a VmOperationResult);
+
+impl<'a> Tabled for ResultRow<'a> {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        vec![
+            Cow::from(self.0.name.as_str()),
+            Cow::from(self.0.group.as_str()),
+            Cow::from(format!("{:.0?}", self.0.elapsed)),
+            Cow::from(self.0.final_state.as_str())
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Name"),
+            Cow::from("Resource Group"),
+            Cow::from("Elapsed"),
+            Cow::from("State")
+        ]
+    }
+}
+
+struct ImageRow<'a>(&'a ImageAlias);
+
+impl<'a> Tabled for ImageRow<'a> {
+    const LENGTH: usize = 5;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let image = &self.0.image;
+        vec![
+            Cow::from(self.0.alias.as_str()),
+            Cow::from(image.publisher.as_deref().unwrap_or("")),
+            Cow::from(image.offer.as_deref().unwrap_or("")),
+            Cow::from(image.sku.as_deref().unwrap_or("")),
+            Cow::from(image.version.as_deref().unwrap_or(""))
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Alias"),
+            Cow::from("Publisher"),
+            Cow::from("Offer"),
+            Cow::from("SKU"),
+            Cow::from("Version")
+        ]
+    }
+}
+
+struct ActionRow<'a>(&'a VmAction);
+
+impl<'a> Tabled for ActionRow<'a> {
+    const LENGTH: usize = 2;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        vec![
+            Cow::from(self.0.name.as_str()),
+            Cow::from(self.0.action.as_str())
+        ]
+    }
+
+    fn headers() -> Vec<Cow<'static, str>> {
+        vec![
+            Cow::from("Name"),
+            Cow::from("Action")
+        ]
+    }
+}
+
 #[derive(Clone)]
 struct Colorization;
 
@@ -101,7 +844,9 @@ impl CellOption<VecRecords<CellInfo<String>>, ColoredConfig> for Colorization {
 fn vm_status_color(status: &str) -> Color {
     match status {
         "VM deallocated" => Color::BG_RED | Color::FG_BLACK,
-        "VM deallocating" | "VM starting" => Color::BG_YELLOW | Color::FG_BLACK,
+        // Stopped but still allocated, and so still billed for compute.
+        "VM stopped" => Color::BG_MAGENTA | Color::FG_BLACK,
+        "VM deallocating" | "VM stopping" | "VM starting" => Color::BG_YELLOW | Color::FG_BLACK,
         "VM running" => Color::BG_GREEN | Color::FG_BLACK,
         _ => Color::default(),
     }
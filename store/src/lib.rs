@@ -1,56 +1,498 @@
 use serde::{Deserialize, Serialize};
+use directories::ProjectDirs;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
 
-const STORE_FILE: &'static str = "store.json";
+const STORE_FILE: &str = "store.json";
+const CONFIG_HOME_VAR: &str = "AZVM_CONFIG_HOME";
+const DEFAULT_PROFILE: &str = "default";
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub struct Store{
+const SUBSCRIPTION_VAR: &str = "AZVM_SUBSCRIPTION_ID";
+const RESOURCE_GROUP_VAR: &str = "AZVM_RESOURCE_GROUP";
+const VAULT_NAME_VAR: &str = "AZVM_VAULT_NAME";
+const VAULT_RG_VAR: &str = "AZVM_VAULT_RG";
+
+/// Resolves the location of the store file.
+///
+/// `$AZVM_CONFIG_HOME` takes precedence when set. Otherwise a `store.json` left
+/// in the current directory by older versions is used if it exists, falling back
+/// to the platform config directory (e.g. `~/.config/azvm/store.json` on Linux).
+pub fn store_path() -> PathBuf {
+    if let Some(dir) = env::var_os(CONFIG_HOME_VAR) {
+        return PathBuf::from(dir).join(STORE_FILE);
+    }
+
+    let legacy = PathBuf::from(STORE_FILE);
+    if legacy.exists() {
+        return legacy;
+    }
+
+    match ProjectDirs::from("", "", "azvm") {
+        Some(dirs) => dirs.config_dir().join(STORE_FILE),
+        None => legacy
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    NoProfile(String),
+    ProfileExists(String),
+    Corrupt(PathBuf, serde_json::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Corrupt(_, e) => Some(e),
+            StoreError::Io(e) => Some(e),
+            StoreError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NoProfile(name) => write!(f, "Profile '{name}' does not exist"),
+            StoreError::ProfileExists(name) => write!(f, "Profile '{name}' already exists"),
+            StoreError::Corrupt(path, e) => {
+                write!(f, "Store file {} is invalid ({e}), fix or remove it to continue", path.display())
+            },
+            StoreError::Io(e) => write!(f, "Failed to access store file: {e}"),
+            StoreError::Json(e) => write!(f, "Failed to parse store file: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Json(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Profile {
     resource_group: Option<String>,
-    subscription_id: Option<String>
+    subscription_id: Option<String>,
+    vault_name: Option<String>,
+    vault_resource_group: Option<String>,
+
+    /// The resource group last set under each subscription, keyed by
+    /// lowercased subscription id. `resource_group` is the fallback for
+    /// subscriptions without one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    resource_groups: BTreeMap<String, String>,
+
+    /// The VMs targeted by the last successful power command, keyed by
+    /// lowercased resource group name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    last_vm_names: BTreeMap<String, Vec<String>>,
+
+    /// REST API versions to use instead of the Azure crates' own, keyed by
+    /// service name (e.g. `compute`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    api_versions: BTreeMap<String, String>
 }
 
-impl Store {
-    pub async fn get_or_create() -> Result<Self, Box<dyn std::error::Error>> {
-        match Self::get_store().await {
-            Ok(store) => Ok(store),
-            Err(_) => {
-                let store = Self::default();
-                store.save().await?;
-                Ok(store)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Store{
+    active_profile: String,
+    profiles: BTreeMap<String, Profile>,
+
+    /// Profile selected for this invocation only, never persisted.
+    #[serde(skip)]
+    profile_override: Option<String>,
+
+    /// Settings read from `AZVM_*` environment variables or overridden for
+    /// this invocation, which take precedence over the active profile and
+    /// are never persisted.
+    #[serde(skip)]
+    env: Profile
+}
+
+/// The on-disk formats we know how to read. Files written before profiles
+/// existed hold a single flat profile which is migrated into `default`.
+enum StoreFile {
+    Profiles(Store),
+    Legacy(Profile)
+}
+
+impl From<StoreFile> for Store {
+    fn from(file: StoreFile) -> Self {
+        match file {
+            StoreFile::Profiles(store) => store,
+            StoreFile::Legacy(profile) => Self {
+                active_profile: DEFAULT_PROFILE.to_owned(),
+                profiles: BTreeMap::from([(DEFAULT_PROFILE.to_owned(), profile)]),
+                profile_override: None,
+                env: Profile::default()
             }
         }
     }
+}
 
-    pub async fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let contents = serde_json::to_string(self)?;
-        fs::write(STORE_FILE, contents).await?;
+impl Default for Store {
+    fn default() -> Self {
+        StoreFile::Legacy(Profile::default()).into()
+    }
+}
+
+impl Store {
+    /// Loads the store, creating a default one if the file doesn't exist.
+    /// A file that exists but can't be parsed is an error rather than being
+    /// replaced, so hand edits gone wrong don't lose the configuration.
+    pub async fn get_or_create() -> Result<Self, StoreError> {
+        let mut store = Self::load(&store_path()).await?;
+        store.env = Profile {
+            resource_group: env_setting(RESOURCE_GROUP_VAR),
+            subscription_id: env_setting(SUBSCRIPTION_VAR),
+            vault_name: env_setting(VAULT_NAME_VAR),
+            vault_resource_group: env_setting(VAULT_RG_VAR),
+            ..Profile::default()
+        };
+        Ok(store)
+    }
+
+    pub async fn save(&self) -> Result<(), StoreError> {
+        self.save_to(&store_path()).await
+    }
+
+    pub fn create_profile(&mut self, name: &str) -> Result<(), StoreError> {
+        if self.profiles.contains_key(name) {
+            return Err(StoreError::ProfileExists(name.to_owned()));
+        }
+        self.profiles.insert(name.to_owned(), Profile::default());
+        Ok(())
+    }
+
+    pub fn switch_profile(&mut self, name: &str) -> Result<(), StoreError> {
+        if !self.profiles.contains_key(name) {
+            return Err(StoreError::NoProfile(name.to_owned()));
+        }
+        self.active_profile = name.to_owned();
         Ok(())
     }
 
+    /// Uses the named profile for the lifetime of this `Store` without
+    /// changing the persisted active profile.
+    pub fn override_profile(&mut self, name: &str) -> Result<(), StoreError> {
+        if !self.profiles.contains_key(name) {
+            return Err(StoreError::NoProfile(name.to_owned()));
+        }
+        self.profile_override = Some(name.to_owned());
+        Ok(())
+    }
+
+    /// Uses `subscription_id` for the lifetime of this `Store`, ahead of the
+    /// environment and the active profile, without persisting it.
+    pub fn override_subscription_id(&mut self, subscription_id: &str) {
+        self.env.subscription_id = Some(subscription_id.to_owned());
+    }
+
+    /// Uses `resource_group` for the lifetime of this `Store`, ahead of the
+    /// environment and the active profile, without persisting it.
+    pub fn override_resource_group(&mut self, resource_group: &str) {
+        self.env.resource_group = Some(resource_group.to_owned());
+    }
+
+    pub fn list_profiles(&self) -> Vec<&str> {
+        self.profiles.keys().map(|name| name.as_str()).collect()
+    }
+
+    pub fn get_active_profile(&self) -> &str {
+        self.profile_override.as_deref().unwrap_or(&self.active_profile)
+    }
+
+    /// Resets every setting of the active profile.
+    pub fn clear(&mut self) {
+        *self.profile_mut() = Profile::default();
+    }
+
+    /// Sets the resource group, remembering it for the current subscription
+    /// as well so switching back to that subscription selects it again.
     pub fn set_resource_group(&mut self, resource_group: &str) {
-        self.resource_group = Some(resource_group.to_owned());
+        let subscription_id = self.get_subscription_id().map(str::to_lowercase);
+        let profile = self.profile_mut();
+        if let Some(subscription_id) = subscription_id {
+            profile.resource_groups.insert(subscription_id, resource_group.to_owned());
+        }
+        profile.resource_group = Some(resource_group.to_owned());
     }
 
+    /// The resource group from the environment, else the one remembered for
+    /// the current subscription, else the last one set.
     pub fn get_resource_group(&self) -> Option<&str> {
-        self.resource_group.as_deref()
+        let subscription_id = self.get_subscription_id().map(str::to_lowercase);
+        self.env.resource_group.as_deref()
+            .or_else(|| self.profile().and_then(|p| subscription_id
+                .and_then(|id| p.resource_groups.get(&id))
+                .or(p.resource_group.as_ref())
+                .map(|group| group.as_str())))
     }
 
     pub fn set_subscription_id(&mut self, subscription_id: &str) {
-        self.subscription_id = Some(subscription_id.to_owned());
+        self.profile_mut().subscription_id = Some(subscription_id.to_owned());
     }
 
     pub fn get_subscription_id(&self) -> Option<&str> {
-        self.subscription_id.as_deref()
+        self.env.subscription_id.as_deref()
+            .or_else(|| self.profile().and_then(|p| p.subscription_id.as_deref()))
+    }
+
+    pub fn set_vault_name(&mut self, vault_name: &str) {
+        self.profile_mut().vault_name = Some(vault_name.to_owned());
     }
 
-    async fn get_store() -> Result<Store, Box<dyn std::error::Error>> {
-        let contents = fs::read_to_string(STORE_FILE).await?;
-        Ok(serde_json::from_str::<Store>(&contents)?)
+    pub fn get_vault_name(&self) -> Option<&str> {
+        self.env.vault_name.as_deref()
+            .or_else(|| self.profile().and_then(|p| p.vault_name.as_deref()))
     }
+
+    pub fn set_vault_resource_group(&mut self, vault_resource_group: &str) {
+        self.profile_mut().vault_resource_group = Some(vault_resource_group.to_owned());
+    }
+
+    pub fn get_vault_resource_group(&self) -> Option<&str> {
+        self.env.vault_resource_group.as_deref()
+            .or_else(|| self.profile().and_then(|p| p.vault_resource_group.as_deref()))
+    }
+
+    pub fn set_last_vm_names(&mut self, resource_group: &str, vm_names: &[String]) {
+        self.profile_mut().last_vm_names.insert(resource_group.to_lowercase(), vm_names.to_vec());
+    }
+
+    pub fn get_last_vm_names(&self, resource_group: &str) -> Option<&[String]> {
+        self.profile()
+            .and_then(|p| p.last_vm_names.get(&resource_group.to_lowercase()))
+            .map(|names| names.as_slice())
+    }
+
+    /// Overrides the API version of `service`, or goes back to the default
+    /// when `version` is empty.
+    pub fn set_api_version(&mut self, service: &str, version: &str) {
+        let api_versions = &mut self.profile_mut().api_versions;
+        match version.is_empty() {
+            true => api_versions.remove(service),
+            false => api_versions.insert(service.to_owned(), version.to_owned())
+        };
+    }
+
+    pub fn get_api_versions(&self) -> BTreeMap<&str, &str> {
+        self.profile()
+            .map(|p| p.api_versions.iter().map(|(service, version)| (service.as_str(), version.as_str())).collect())
+            .unwrap_or_default()
+    }
+
+    fn profile(&self) -> Option<&Profile> {
+        self.profiles.get(self.get_active_profile())
+    }
+
+    fn profile_mut(&mut self) -> &mut Profile {
+        let name = self.get_active_profile().to_owned();
+        self.profiles.entry(name).or_default()
+    }
+
+    /// Copies the current store file to `store.json.bak`, if there is one.
+    pub async fn backup(&self) -> Result<(), StoreError> {
+        backup(&store_path()).await
+    }
+
+    async fn load(path: &Path) -> Result<Self, StoreError> {
+        match fs::read_to_string(path).await {
+            Ok(contents) => Self::parse(path, &contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let store = Self::default();
+                store.save_to(path).await?;
+                Ok(store)
+            },
+            Err(e) => Err(e.into())
+        }
+    }
+
+    async fn save_to(&self, path: &Path) -> Result<(), StoreError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).await?;
+        }
+
+        backup(path).await?;
+
+        // Write to a sibling file and rename it into place so an interrupted
+        // save can't leave a truncated store behind.
+        let contents = serde_json::to_string(self)?;
+        let temp = temp_path(path);
+        fs::write(&temp, contents).await?;
+        if let Err(e) = fs::rename(&temp, path).await {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Parses the store, picking the format by its keys so that a profiles
+    /// file with a bad value is reported rather than read as an empty legacy
+    /// profile, which the next save would write over every profile.
+    fn parse(path: &Path, contents: &str) -> Result<Store, StoreError> {
+        let corrupt = |e| StoreError::Corrupt(path.to_owned(), e);
+        let value: serde_json::Value = serde_json::from_str(contents).map_err(corrupt)?;
+        let legacy = value.as_object()
+            .is_some_and(|keys| !keys.contains_key("active_profile") && !keys.contains_key("profiles"));
+
+        // Parse the text again rather than the value so errors keep their line.
+        let file = match legacy {
+            true => StoreFile::Legacy(serde_json::from_str(contents).map_err(corrupt)?),
+            false => StoreFile::Profiles(serde_json::from_str(contents).map_err(corrupt)?)
+        };
+        Ok(file.into())
+    }
+}
+
+async fn backup(path: &Path) -> Result<(), StoreError> {
+    match fs::copy(path, backup_path(path)).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into())
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    with_suffix(path, &format!(".{}.tmp", std::process::id()))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Reads a setting from the environment, treating an empty value as unset.
+fn env_setting(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store path in an empty directory of its own.
+    fn temp_store(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("azvm-store-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(STORE_FILE)
+    }
 
+    #[tokio::test]
+    async fn creates_a_default_store_when_missing() {
+        let path = temp_store("missing");
+        let store = Store::load(&path).await.unwrap();
+
+        assert_eq!(store.get_active_profile(), DEFAULT_PROFILE);
+        assert_eq!(store.list_profiles(), [DEFAULT_PROFILE]);
+        assert!(path.exists());
+    }
 
+    #[tokio::test]
+    async fn reports_a_corrupt_store_without_replacing_it() {
+        let path = temp_store("corrupt");
+        std::fs::write(&path, "{\"active_profile\": ").unwrap();
 
+        let e = Store::load(&path).await.unwrap_err();
+        assert!(matches!(&e, StoreError::Corrupt(p, _) if *p == path));
+        assert!(e.to_string().contains(&path.display().to_string()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"active_profile\": ");
+    }
+
+    #[tokio::test]
+    async fn reports_a_bad_value_in_a_profiles_store_instead_of_reading_it_as_legacy() {
+        let path = temp_store("bad-value");
+        let contents = r#"{"active_profile":"work","profiles":{"work":{"subscription_id":42}}}"#;
+        std::fs::write(&path, contents).unwrap();
+
+        assert!(matches!(Store::load(&path).await, Err(StoreError::Corrupt(..))));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+    }
 
+    #[tokio::test]
+    async fn save_backs_up_the_previous_store() {
+        let path = temp_store("backup");
+        let mut store = Store::load(&path).await.unwrap();
+        let previous = std::fs::read_to_string(&path).unwrap();
+
+        store.set_subscription_id("sub");
+        store.save_to(&path).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(backup_path(&path)).unwrap(), previous);
+        assert_eq!(Store::load(&path).await.unwrap().get_subscription_id(), Some("sub"));
+    }
+
+    #[test]
+    fn migrates_a_legacy_store_into_the_default_profile() {
+        let contents = r#"{"resource_group":"rg","subscription_id":"sub","vault_name":"vault","vault_resource_group":"vault-rg"}"#;
+        let store = Store::parse(Path::new(STORE_FILE), contents).unwrap();
+
+        assert_eq!(store.get_active_profile(), DEFAULT_PROFILE);
+        assert_eq!(store.list_profiles(), [DEFAULT_PROFILE]);
+        assert_eq!(store.get_resource_group(), Some("rg"));
+        assert_eq!(store.get_subscription_id(), Some("sub"));
+        assert_eq!(store.get_vault_name(), Some("vault"));
+        assert_eq!(store.get_vault_resource_group(), Some("vault-rg"));
+    }
+
+    #[test]
+    fn creates_and_switches_profiles() {
+        let mut store = Store::default();
+        store.set_subscription_id("default-sub");
+        store.create_profile("work").unwrap();
+
+        assert!(matches!(store.create_profile("work"), Err(StoreError::ProfileExists(_))));
+        assert!(matches!(store.switch_profile("home"), Err(StoreError::NoProfile(_))));
+        assert_eq!(store.list_profiles(), [DEFAULT_PROFILE, "work"]);
+
+        store.switch_profile("work").unwrap();
+        assert_eq!(store.get_active_profile(), "work");
+        assert_eq!(store.get_subscription_id(), None);
+
+        store.switch_profile(DEFAULT_PROFILE).unwrap();
+        assert_eq!(store.get_subscription_id(), Some("default-sub"));
+    }
+
+    #[test]
+    fn overrides_take_precedence_without_being_saved() {
+        let mut store = Store::default();
+        store.set_subscription_id("default-sub");
+        store.create_profile("work").unwrap();
+        store.switch_profile("work").unwrap();
+        store.set_subscription_id("work-sub");
+        store.switch_profile(DEFAULT_PROFILE).unwrap();
+
+        assert!(matches!(store.override_profile("home"), Err(StoreError::NoProfile(_))));
+        store.override_profile("work").unwrap();
+        assert_eq!(store.get_active_profile(), "work");
+        assert_eq!(store.get_subscription_id(), Some("work-sub"));
+
+        store.override_subscription_id("flag-sub");
+        assert_eq!(store.get_subscription_id(), Some("flag-sub"));
+
+        let saved: serde_json::Value = serde_json::to_value(&store).unwrap();
+        assert_eq!(saved["active_profile"], DEFAULT_PROFILE);
+        assert_eq!(saved["profiles"]["work"]["subscription_id"], "work-sub");
+    }
+}